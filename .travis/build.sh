@@ -2,7 +2,12 @@
 
 if [ "$TRAVIS_OS_NAME" == "linux" ]; then
     sudo apt-get update
-    sudo apt-get install -y libasound2-dev libudev-dev pkg-config
+    sudo apt-get install -y libasound2-dev libudev-dev pkg-config blender
+fi
+
+if [ "$TRAVIS_OS_NAME" == "osx" ]; then
+    brew install --cask blender
+    export BLENDER=/Applications/Blender.app/Contents/MacOS/Blender
 fi
 
 if [ "$TRAVIS_OS_NAME" == 'windows' ]; then
@@ -24,11 +29,8 @@ if [ "$TRAVIS_OS_NAME" == 'windows' ]; then
 else
     # osx and linux
 
-    # For releases, we have gen-resources checked in.
-    # Make those files newer than their source files.
-    if [ -d gen-resources ]; then
-        echo 'Make files in gen-resources up to date'
-        touch gen-resources/*
-    fi
+    # Convert the levels again if they are older than the converter, so
+    # they are checked and released in the format the game reads
+    make check-levels
     make release
 fi
@@ -8,10 +8,33 @@ from bpy import context as C
 from mathutils import *
 from math import *
 
+# Collision map pixels per world unit, unless the Extents object has a
+# "resolution" property
+DEFAULT_RESOLUTION = 1.024
+
+# Custom properties of the scene that override the game's physics constants
+PHYSICS_PROPERTIES = ('gravity', 'thrust', 'turn_speed', 'energy_conservation')
+
+# Values of the "falloff" property of gravity wells, in the order of the
+# Falloff enum in src/meshes.rs
+FALLOFFS = ('inverse_square', 'linear')
+
+# Values of the "shape" property of triggers, in the order of the
+# TriggerShape enum in src/meshes.rs
+TRIGGER_SHAPES = ('rectangle', 'circle', 'polygon')
+
 
 def convert(objects, *, include, exclude):
     polygons = []
     triggers = {}
+    extents = None
+    zones = []
+    wells = []
+    wind = []
+    obstacles = []
+    # Name -> (enabled, polygons)
+    groups = {}
+    pickups = []
 
     for obj in objects:
         if obj.type != 'MESH':
@@ -29,12 +52,150 @@ def convert(objects, *, include, exclude):
         min_z = min(c[2] for c in bounds)
         max_z = max(c[2] for c in bounds)
 
+        if obj.name == 'Extents':
+            # Not a polygon, just defines the area of the level
+            resolution = obj.get('resolution', DEFAULT_RESOLUTION)
+            extents = (
+                min_x, max_x, min_z, max_z,
+                round((max_x - min_x) * resolution),
+                round((max_z - min_z) * resolution),
+            )
+            continue
+
+        if obj.name == 'Zone' or obj.name.startswith('Zone_'):
+            # Each face is an area with the physics given by the custom
+            # properties gravity_x and gravity_y (both or none), drag and thrust
+            if 'gravity_x' in obj or 'gravity_y' in obj:
+                gravity = (obj.get('gravity_x', 0.0), obj.get('gravity_y', 0.0))
+            else:
+                gravity = None
+            drag = obj.get('drag', 0.0)
+            thrust = obj.get('thrust', 1.0)
+            mesh = obj.to_mesh()
+            for poly in mesh.polygons:
+                vertices = [
+                    mat @ mesh.vertices[mesh.loops[i].vertex_index].co
+                    for i in poly.loop_indices
+                ]
+                zones.append((vertices, gravity, drag, thrust))
+            continue
+
+        if obj.name == 'Well' or obj.name.startswith('Well_'):
+            # Gravity well at the center of the object. The radius is
+            # half the object's width unless given as a custom property.
+            falloff = obj.get('falloff', 'inverse_square')
+            if falloff not in FALLOFFS:
+                raise RuntimeError('Unknown falloff for %s: %s' % (obj.name, falloff))
+            wells.append((
+                (min_x + max_x) * 0.5, (min_z + max_z) * 0.5,
+                obj.get('strength', 40.0),
+                obj.get('radius', (max_x - min_x) * 0.5),
+                FALLOFFS.index(falloff),
+            ))
+            continue
+
+        if obj.name == 'Wind' or obj.name.startswith('Wind_'):
+            # Each face is an area with wind given by the custom properties
+            # force_x, force_y, turbulence and seed
+            force = (obj.get('force_x', 0.0), obj.get('force_y', 0.0))
+            turbulence = obj.get('turbulence', 0.0)
+            seed = obj.get('seed', 0)
+            mesh = obj.to_mesh()
+            for poly in mesh.polygons:
+                vertices = [
+                    mat @ mesh.vertices[mesh.loops[i].vertex_index].co
+                    for i in poly.loop_indices
+                ]
+                wind.append((vertices, force, turbulence, seed))
+            continue
+
+        if obj.name.startswith('Path_'):
+            # Used by obstacles
+            continue
+
+        if obj.name == 'Obstacle' or obj.name.startswith('Obstacle_'):
+            # Each face is a polygon that moves with the object's origin.
+            # Custom properties: path (name of an object whose vertices are
+            # the points to move between), speed and rotation_speed (degrees
+            # per second).
+            pivot = obj.matrix_world.translation
+            path_name = obj.get('path')
+            if path_name is None:
+                path = [(pivot[0], pivot[2])]
+            else:
+                path_obj = D.objects[path_name]
+                path = [
+                    (v[0], v[2])
+                    for v in (path_obj.matrix_world @ vert.co
+                              for vert in path_obj.data.vertices)
+                ]
+            speed = obj.get('speed', 0.0)
+            rotation_speed = radians(obj.get('rotation_speed', 0.0))
+            mesh = obj.to_mesh()
+            for poly in mesh.polygons:
+                vertices = [
+                    mat @ mesh.vertices[mesh.loops[i].vertex_index].co - pivot
+                    for i in poly.loop_indices
+                ]
+                obstacles.append((vertices, path, speed, rotation_speed))
+            continue
+
+        if obj.name.startswith('Pickup_'):
+            # Something to collect, with the size of the object
+            _, s = obj.name.split('_', 1)
+            pickups.append((
+                int(s, 0),
+                (min_x + max_x) * 0.5, (min_z + max_z) * 0.5,
+                (max_x - min_x) * 0.5,
+            ))
+            continue
+
         if obj.name.startswith('Trigger_'):
+            # The custom property "shape" is rectangle (default), circle
+            # (fills the bounding box) or polygon (the first face).
+            # With "whole_ship" set, any part of the ship sets it off.
+            # Of triggers that fire at the same time, the one with the highest
+            # "priority" (integer, default 0) goes first.
             _, s = obj.name.split('_', 1)
             trigger_id = int(s, 0)
             if trigger_id in triggers:
                 raise RuntimeError('Duplicate trigger: %s' % trigger_id)
-            triggers[trigger_id] = (min_x, max_x, min_z, max_z)
+            shape = obj.get('shape', 'rectangle')
+            if shape not in TRIGGER_SHAPES:
+                raise RuntimeError('Invalid shape of %s: %s' % (obj.name, shape))
+            polygon = None
+            if shape == 'polygon':
+                mesh = obj.to_mesh()
+                if not mesh.polygons:
+                    raise RuntimeError('%s has no face' % obj.name)
+                poly = mesh.polygons[0]
+                polygon = []
+                for loop_index in poly.loop_indices:
+                    v = mat @ mesh.vertices[mesh.loops[loop_index].vertex_index].co
+                    # Relative to the bounding box
+                    polygon.append((
+                        (v[0] - min_x) / (max_x - min_x),
+                        (v[2] - min_z) / (max_z - min_z),
+                    ))
+            triggers[trigger_id] = (
+                (min_x, max_x, min_z, max_z),
+                TRIGGER_SHAPES.index(shape), polygon,
+                bool(obj.get('whole_ship', 0)),
+                int(obj.get('priority', 0)),
+            )
+            continue
+
+        # Walls in a group can be turned on and off by the script.
+        # The group is off at the start if any of its objects has the
+        # custom property "hidden" set.
+        group_name = obj.get('group')
+        if group_name is not None:
+            enabled, group_polygons = groups.get(group_name, (True, []))
+            if obj.get('hidden', 0):
+                enabled = False
+            groups[group_name] = (enabled, group_polygons)
+        else:
+            group_polygons = polygons
 
         mesh = obj.to_mesh()
 
@@ -47,9 +208,14 @@ def convert(objects, *, include, exclude):
                 v = mat @ mesh.vertices[mesh.loops[loop_index].vertex_index].co
                 vertices.append(v)
 
-            polygons.append(vertices)
+            group_polygons.append(vertices)
+
+    return (polygons, triggers, extents, zones, wells, wind, obstacles, groups,
+            pickups)
+
 
-    return polygons, triggers
+def get_physics(scene):
+    return [scene.get(name) for name in PHYSICS_PROPERTIES]
 
 
 def write_usize(out, value):
@@ -57,7 +223,21 @@ def write_usize(out, value):
     out.write(struct.pack('<II', value, 0))
 
 
-def export(polygons, triggers, out):
+def write_option_f32(out, value):
+    if value is None:
+        out.write(struct.pack('<B', 0))
+    else:
+        out.write(struct.pack('<Bf', 1, value))
+
+
+def write_polygon(out, vertices):
+    write_usize(out, len(vertices))
+    for v in vertices:
+        out.write(struct.pack('<ff', v[0], v[2]))
+
+
+def export(polygons, triggers, extents, physics, zones, wells, wind,
+           obstacles, groups, pickups, out):
     write_usize(out, len(polygons))
     for polygon in polygons:
         vertices = polygon
@@ -66,8 +246,65 @@ def export(polygons, triggers, out):
             out.write(struct.pack('ff', v[0], v[2]))
 
     write_usize(out, len(triggers))
-    for trigger_id, bounds in triggers.items():
+    for trigger_id, (bounds, shape, polygon, whole_ship,
+                     priority) in triggers.items():
         out.write(struct.pack('<Iffff', trigger_id, *bounds))
+        out.write(struct.pack('<I', shape))
+        if polygon is not None:
+            write_usize(out, len(polygon))
+            for u, v in polygon:
+                out.write(struct.pack('<ff', u, v))
+        out.write(struct.pack('<Bi', whole_ship, priority))
+
+    # Option<Extents>
+    if extents is None:
+        out.write(struct.pack('<B', 0))
+    else:
+        out.write(struct.pack('<BffffII', 1, *extents))
+
+    # Physics
+    for value in physics:
+        write_option_f32(out, value)
+
+    write_usize(out, len(zones))
+    for vertices, gravity, drag, thrust in zones:
+        write_polygon(out, vertices)
+        if gravity is None:
+            out.write(struct.pack('<B', 0))
+        else:
+            out.write(struct.pack('<Bff', 1, *gravity))
+        out.write(struct.pack('<ff', drag, thrust))
+
+    write_usize(out, len(wells))
+    for well in wells:
+        out.write(struct.pack('<ffffI', *well))
+
+    write_usize(out, len(wind))
+    for vertices, force, turbulence, seed in wind:
+        write_polygon(out, vertices)
+        out.write(struct.pack('<fffI', *force, turbulence, seed))
+
+    write_usize(out, len(obstacles))
+    for vertices, path, speed, rotation_speed in obstacles:
+        write_polygon(out, vertices)
+        write_usize(out, len(path))
+        for p in path:
+            out.write(struct.pack('<ff', *p))
+        out.write(struct.pack('<ff', speed, rotation_speed))
+
+    write_usize(out, len(groups))
+    for name, (enabled, group_polygons) in groups.items():
+        encoded_name = name.encode('utf-8')
+        write_usize(out, len(encoded_name))
+        out.write(encoded_name)
+        write_usize(out, len(group_polygons))
+        for vertices in group_polygons:
+            write_polygon(out, vertices)
+        out.write(struct.pack('<B', 1 if enabled else 0))
+
+    write_usize(out, len(pickups))
+    for pickup in pickups:
+        out.write(struct.pack('<Ifff', *pickup))
 
 
 def main(args):
@@ -88,12 +325,17 @@ def main(args):
     )
     args = parser.parse_args(args=args)
 
-    polygons, triggers = convert(C.scene.objects, exclude=args.exclude or [],
-                                 include=args.include)
+    (polygons, triggers, extents, zones, wells, wind, obstacles,
+     groups, pickups) = convert(
+        C.scene.objects,
+        exclude=args.exclude or [],
+        include=args.include)
+    physics = get_physics(C.scene)
 
     with open(args.out, 'wb') as out:
         print('Writing', args.out)
-        export(polygons, triggers, out)
+        export(polygons, triggers, extents, physics, zones, wells, wind,
+               obstacles, groups, pickups, out)
 
 
 i = sys.argv.index('--')
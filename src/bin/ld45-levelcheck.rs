@@ -0,0 +1,719 @@
+// Checks level files for problems.
+//
+// Usage: ld45-levelcheck [--scripts DIR] LEVEL.dat...
+//
+// The script for levelNN.dat is read from levelNN.script in the scripts
+// directory, which is `resources` by default.
+// Exits with a non-zero status if any errors were found.
+
+use std::collections::{HashSet, VecDeque};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use ggez::{GameError, GameResult};
+use ld45::meshes::{is_inside_polygon, polygon_area, Extents, RawMeshes, Trigger, TriggerShape};
+use ld45::script::{Action, Script};
+
+// Polygons with a smaller area than this are considered degenerate
+const MIN_AREA: f32 = 0.01;
+
+// Size of the cells when finding where the ship can fly, in world units.
+// Bigger for big levels so the grid has at most MAX_CELLS cells per side.
+const CELL_SIZE: f32 = 4.0;
+const MAX_CELLS: f32 = 1024.0;
+
+struct Report {
+    name: String,
+    errors: u32,
+    warnings: u32,
+}
+
+impl Report {
+    fn error(&mut self, message: String) {
+        println!("{}: error: {}", self.name, message);
+        self.errors += 1;
+    }
+
+    // Report the errors that also stop the game from loading the level, with
+    // the same message
+    fn check(&mut self, result: GameResult) {
+        match result {
+            Ok(()) => {}
+            Err(GameError::ResourceLoadError(message)) => self.error(message),
+            Err(e) => self.error(e.to_string()),
+        }
+    }
+
+    fn warning(&mut self, message: String) {
+        println!("{}: warning: {}", self.name, message);
+        self.warnings += 1;
+    }
+}
+
+// Which side of the line from a to b that c is on
+fn orientation(a: (f32, f32), b: (f32, f32), c: (f32, f32)) -> f32 {
+    (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+}
+
+fn segments_intersect(a0: (f32, f32), a1: (f32, f32), b0: (f32, f32), b1: (f32, f32)) -> bool {
+    let d0 = orientation(a0, a1, b0);
+    let d1 = orientation(a0, a1, b1);
+    let d2 = orientation(b0, b1, a0);
+    let d3 = orientation(b0, b1, a1);
+    if d0 * d1 < 0.0 && d2 * d3 < 0.0 {
+        return true;
+    }
+    // Collinear cases: check if an end point is on the other segment
+    let on_segment = |p: (f32, f32), q0: (f32, f32), q1: (f32, f32)| {
+        p.0 >= q0.0.min(q1.0)
+            && p.0 <= q0.0.max(q1.0)
+            && p.1 >= q0.1.min(q1.1)
+            && p.1 <= q0.1.max(q1.1)
+    };
+    (d0 == 0.0 && on_segment(b0, a0, a1))
+        || (d1 == 0.0 && on_segment(b1, a0, a1))
+        || (d2 == 0.0 && on_segment(a0, b0, b1))
+        || (d3 == 0.0 && on_segment(a1, b0, b1))
+}
+
+fn is_self_intersecting(polygon: &[(f32, f32)]) -> bool {
+    let n = polygon.len();
+    for i in 0..n {
+        for j in (i + 2)..n {
+            if i == 0 && j == n - 1 {
+                // Adjacent through the closing edge
+                continue;
+            }
+            if segments_intersect(
+                polygon[i],
+                polygon[(i + 1) % n],
+                polygon[j],
+                polygon[(j + 1) % n],
+            ) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+fn is_inside_level(trigger: &Trigger, extents: &Extents) -> bool {
+    trigger.min_x >= extents.min_x
+        && trigger.max_x <= extents.max_x
+        && trigger.min_y >= extents.min_y
+        && trigger.max_y <= extents.max_y
+}
+
+fn check_polygon(report: &mut Report, name: &str, polygon: &[(f32, f32)]) {
+    if polygon.len() < 3 {
+        report.error(format!("{} has only {} vertices", name, polygon.len()));
+    } else if polygon_area(polygon).abs() < MIN_AREA {
+        report.error(format!("{} is degenerate (no area)", name));
+    } else if is_self_intersecting(polygon) {
+        report.error(format!("{} is self-intersecting", name));
+    }
+}
+
+fn check_meshes(report: &mut Report, meshes: &RawMeshes) {
+    let extents = meshes.get_extents();
+    report.check(extents.validate());
+
+    for (i, polygon) in meshes.polygons.iter().enumerate() {
+        check_polygon(report, &format!("Polygon {}", i), polygon);
+    }
+    for group in meshes.groups.iter() {
+        for (i, polygon) in group.polygons.iter().enumerate() {
+            check_polygon(
+                report,
+                &format!("Polygon {} in group {}", i, group.name),
+                polygon,
+            );
+        }
+    }
+
+    for (i, zone) in meshes.zones.iter().enumerate() {
+        if zone.polygon.len() < 3 {
+            report.error(format!(
+                "Zone {} has only {} vertices",
+                i,
+                zone.polygon.len()
+            ));
+        }
+        report.check(zone.validate(i));
+    }
+    for (i, field) in meshes.wind.iter().enumerate() {
+        if field.polygon.len() < 3 {
+            report.error(format!(
+                "Wind field {} has only {} vertices",
+                i,
+                field.polygon.len()
+            ));
+        }
+    }
+    for (i, obstacle) in meshes.obstacles.iter().enumerate() {
+        if obstacle.polygon.len() < 3 {
+            report.error(format!(
+                "Obstacle {} has only {} vertices",
+                i,
+                obstacle.polygon.len()
+            ));
+        }
+        if obstacle.path.is_empty() {
+            report.error(format!("Obstacle {} has no path", i));
+        }
+    }
+    for (i, well) in meshes.wells.iter().enumerate() {
+        if well.radius <= 0.0 {
+            report.error(format!("Gravity well {} has radius {}", i, well.radius));
+        }
+    }
+    report.check(meshes.physics.validate());
+    if let Some(e) = meshes.physics.energy_conservation {
+        if e <= 0.0 || e > 1.0 {
+            report.error(format!(
+                "Energy conservation is {}, must be more than 0 and at most 1",
+                e
+            ));
+        }
+    }
+
+    let mut ids = HashSet::new();
+    for trigger in meshes.triggers.iter() {
+        if !ids.insert(trigger.id) {
+            report.error(format!("Duplicate trigger ID {}", trigger.id));
+        }
+        if !is_inside_level(trigger, &extents) {
+            report.error(format!(
+                "Trigger {} is outside the level extents {:?}",
+                trigger.id, extents
+            ));
+        }
+        if let TriggerShape::Polygon(polygon) = &trigger.shape {
+            if polygon.len() < 3 {
+                report.error(format!(
+                    "Trigger {} has a polygon with {} vertices",
+                    trigger.id,
+                    polygon.len()
+                ));
+            }
+        }
+    }
+    if !ids.contains(&0) {
+        report.error("No spawn point (trigger 0)".to_string());
+    }
+
+    let mut pickup_ids = HashSet::new();
+    for pickup in meshes.pickups.iter() {
+        if !pickup_ids.insert(pickup.id) {
+            report.error(format!("Duplicate pickup ID {}", pickup.id));
+        }
+    }
+}
+
+fn check_script(report: &mut Report, meshes: &RawMeshes, script: &Script) {
+    let mut scripted: Vec<u32> = script.get_trigger_ids().collect();
+    scripted.sort_unstable();
+    for trigger in meshes.triggers.iter() {
+        if trigger.id != 0 && script.get_actions(trigger.id).is_none() {
+            report.error(format!(
+                "Trigger {} is not referenced by the script",
+                trigger.id
+            ));
+        }
+    }
+    for id in scripted {
+        if !meshes.triggers.iter().any(|t| t.id == id) {
+            report.warning(format!("Script refers to missing trigger {}", id));
+        }
+    }
+    for trigger in meshes.triggers.iter() {
+        for visited in script.get_options(trigger.id).visited.iter() {
+            if !meshes.triggers.iter().any(|t| t.id == *visited) {
+                report.error(format!(
+                    "Trigger {} waits for missing trigger {}",
+                    trigger.id, visited
+                ));
+            }
+        }
+    }
+    for (id, action) in script.get_all_actions() {
+        let group = match action {
+            Action::ShowGroup(name) | Action::HideGroup(name) | Action::ToggleGroup(name) => name,
+            Action::Require(Some(count), _) if *count as usize > meshes.pickups.len() => {
+                report.error(format!(
+                    "Trigger {} requires {} pickups but there are only {}",
+                    id,
+                    count,
+                    meshes.pickups.len()
+                ));
+                continue;
+            }
+            _ => continue,
+        };
+        if !meshes.groups.iter().any(|g| &g.name == group) {
+            report.error(format!("Trigger {} refers to missing group {}", id, group));
+        }
+    }
+}
+
+// Grid of the level where true means there is a wall
+struct Walls {
+    min_x: f32,
+    min_y: f32,
+    cell_size: f32,
+    width: usize,
+    height: usize,
+    cells: Vec<bool>,
+}
+
+impl Walls {
+    // Walls of groups that are off at the start and moving obstacles are
+    // not included
+    fn new(meshes: &RawMeshes) -> Walls {
+        let extents = meshes.get_extents();
+        let cell_size = CELL_SIZE
+            .max((extents.max_x - extents.min_x) / MAX_CELLS)
+            .max((extents.max_y - extents.min_y) / MAX_CELLS);
+        let width = ((extents.max_x - extents.min_x) / cell_size).ceil() as usize;
+        let height = ((extents.max_y - extents.min_y) / cell_size).ceil() as usize;
+        let mut walls = Walls {
+            min_x: extents.min_x,
+            min_y: extents.min_y,
+            cell_size,
+            width,
+            height,
+            cells: vec![false; width * height],
+        };
+        let polygons = meshes.polygons.iter().chain(
+            meshes
+                .groups
+                .iter()
+                .filter(|g| g.enabled)
+                .flat_map(|g| g.polygons.iter()),
+        );
+        for polygon in polygons {
+            let cell_range = |values: &mut dyn Iterator<Item = f32>, min: f32, count: usize| {
+                let (low, high) = values.fold((f32::INFINITY, f32::NEG_INFINITY), |(l, h), v| {
+                    (l.min(v), h.max(v))
+                });
+                let first = ((low - min) / cell_size).floor().max(0.0) as usize;
+                let last = (((high - min) / cell_size).ceil().max(0.0) as usize).min(count);
+                first..last
+            };
+            let xs = cell_range(&mut polygon.iter().map(|p| p.0), walls.min_x, width);
+            let ys = cell_range(&mut polygon.iter().map(|p| p.1), walls.min_y, height);
+            for cy in ys {
+                for cx in xs.clone() {
+                    let (x, y) = walls.get_center(cx, cy);
+                    if is_inside_polygon(polygon, x, y) {
+                        walls.cells[cy * width + cx] = true;
+                    }
+                }
+            }
+        }
+        walls
+    }
+
+    fn get_center(&self, cx: usize, cy: usize) -> (f32, f32) {
+        (
+            self.min_x + (cx as f32 + 0.5) * self.cell_size,
+            self.min_y + (cy as f32 + 0.5) * self.cell_size,
+        )
+    }
+
+    fn is_wall(&self, x: f32, y: f32) -> bool {
+        let cx = ((x - self.min_x) / self.cell_size).floor();
+        let cy = ((y - self.min_y) / self.cell_size).floor();
+        cx >= 0.0
+            && cy >= 0.0
+            && (cx as usize) < self.width
+            && (cy as usize) < self.height
+            && self.cells[cy as usize * self.width + cx as usize]
+    }
+
+    // Cells that can be reached from a position without going through a
+    // wall or a blocked cell
+    fn flood_fill(&self, start: (f32, f32), blocked: impl Fn(f32, f32) -> bool) -> Vec<bool> {
+        let mut reached = vec![false; self.cells.len()];
+        let cx = ((start.0 - self.min_x) / self.cell_size).floor();
+        let cy = ((start.1 - self.min_y) / self.cell_size).floor();
+        if cx < 0.0 || cy < 0.0 || cx as usize >= self.width || cy as usize >= self.height {
+            return reached;
+        }
+        let mut queue = VecDeque::new();
+        queue.push_back((cx as usize, cy as usize));
+        while let Some((cx, cy)) = queue.pop_front() {
+            let index = cy * self.width + cx;
+            if reached[index] || self.cells[index] {
+                continue;
+            }
+            let (x, y) = self.get_center(cx, cy);
+            if blocked(x, y) {
+                continue;
+            }
+            reached[index] = true;
+            if cx > 0 {
+                queue.push_back((cx - 1, cy));
+            }
+            if cx + 1 < self.width {
+                queue.push_back((cx + 1, cy));
+            }
+            if cy > 0 {
+                queue.push_back((cx, cy - 1));
+            }
+            if cy + 1 < self.height {
+                queue.push_back((cx, cy + 1));
+            }
+        }
+        reached
+    }
+
+    // Whether any reached cell is inside a trigger
+    fn is_reached(&self, reached: &[bool], trigger: &Trigger) -> bool {
+        let cell = |v: f32, min: f32, count: usize| {
+            (((v - min) / self.cell_size).max(0.0) as usize).min(count)
+        };
+        let xs = cell(trigger.min_x, self.min_x, self.width)
+            ..cell(trigger.max_x, self.min_x, self.width - 1) + 1;
+        let ys = cell(trigger.min_y, self.min_y, self.height)
+            ..cell(trigger.max_y, self.min_y, self.height - 1) + 1;
+        ys.into_iter().any(|cy| {
+            xs.clone().any(|cx| {
+                let (x, y) = self.get_center(cx, cy);
+                reached[cy * self.width + cx] && trigger.contains(x, y)
+            })
+        })
+    }
+}
+
+// The ship respawns at a point inside the spawn point and checkpoints, which
+// must not be in a wall
+fn check_respawn_points(report: &mut Report, meshes: &RawMeshes, script: &Script) {
+    let walls = Walls::new(meshes);
+    for trigger in meshes.triggers.iter() {
+        let is_checkpoint = script
+            .get_actions(trigger.id)
+            .is_some_and(|actions| actions.iter().any(|a| matches!(a, Action::Checkpoint(_))));
+        if trigger.id != 0 && !is_checkpoint {
+            continue;
+        }
+        let (x, y) = trigger.get_inside_point();
+        if !trigger.contains(x, y) {
+            report.error(format!(
+                "Trigger {} has no room inside for the ship to respawn",
+                trigger.id
+            ));
+        } else if walls.is_wall(x, y) {
+            report.error(format!(
+                "Trigger {} respawns the ship in a wall at ({}, {})",
+                trigger.id, x, y
+            ));
+        }
+    }
+}
+
+// Story triggers, i.e. ones that say something and fire once, are meant to
+// be visited in order of ID. Warn if the ship can get to one without passing
+// through the ones before it.
+fn check_sequence(report: &mut Report, meshes: &RawMeshes, script: &Script) {
+    let spawn = match meshes.triggers.iter().find(|t| t.id == 0) {
+        Some(t) => t.get_inside_point(),
+        None => return,
+    };
+    let mut story: Vec<&Trigger> = meshes
+        .triggers
+        .iter()
+        .filter(|t| {
+            t.id != 0
+                && script.get_options(t.id).repeat.is_none()
+                && script.get_actions(t.id).is_some_and(|actions| {
+                    actions
+                        .iter()
+                        .any(|a| matches!(a, Action::Say(_) | Action::Subtitle(_)))
+                })
+        })
+        .collect();
+    story.sort_unstable_by_key(|t| t.id);
+    if story.len() < 2 {
+        return;
+    }
+
+    let walls = Walls::new(meshes);
+    if walls.width == 0 || walls.height == 0 {
+        return;
+    }
+    for (i, earlier) in story.iter().enumerate() {
+        if earlier.contains(spawn.0, spawn.1) {
+            continue;
+        }
+        let reached = walls.flood_fill(spawn, |x, y| earlier.contains(x, y));
+        for later in story[i + 1..].iter() {
+            // The script makes sure of the order
+            if script.get_options(later.id).visited.contains(&earlier.id) {
+                continue;
+            }
+            if walls.is_reached(&reached, later) {
+                report.warning(format!(
+                    "Trigger {} can be reached before trigger {}",
+                    later.id, earlier.id
+                ));
+            }
+        }
+    }
+}
+
+fn check_level(path: &Path, scripts_dir: &Path) -> Report {
+    let mut report = Report {
+        name: path.display().to_string(),
+        errors: 0,
+        warnings: 0,
+    };
+
+    let meshes = match fs::read(path)
+        .map_err(|e| e.to_string())
+        .and_then(|encoded| RawMeshes::decode(&encoded).map_err(|e| e.to_string()))
+    {
+        Ok(meshes) => meshes,
+        Err(e) => {
+            report.error(format!("Could not load level: {}", e));
+            return report;
+        }
+    };
+    println!(
+        "{}: {} polygons, {} groups, {} obstacles, {} triggers, {} pickups, {} zones, {} gravity wells, {} wind fields, {}",
+        report.name,
+        meshes.polygons.len(),
+        meshes.groups.len(),
+        meshes.obstacles.len(),
+        meshes.triggers.len(),
+        meshes.pickups.len(),
+        meshes.zones.len(),
+        meshes.wells.len(),
+        meshes.wind.len(),
+        match meshes.extents {
+            Some(e) => format!(
+                "extents x {} to {}, y {} to {}, collision map {}x{}",
+                e.min_x, e.max_x, e.min_y, e.max_y, e.collision_width, e.collision_height
+            ),
+            None => "default extents".to_string(),
+        }
+    );
+    check_meshes(&mut report, &meshes);
+
+    let script_path = scripts_dir.join(path.with_extension("script").file_name().unwrap());
+    match fs::read_to_string(&script_path)
+        .map_err(|e| e.to_string())
+        .and_then(|source| Script::parse(&source).map_err(|e| e.to_string()))
+    {
+        Ok(script) => {
+            check_script(&mut report, &meshes, &script);
+            check_respawn_points(&mut report, &meshes, &script);
+            check_sequence(&mut report, &meshes, &script);
+        }
+        Err(e) => report.error(format!(
+            "Could not load script {}: {}",
+            script_path.display(),
+            e
+        )),
+    }
+
+    report
+}
+
+fn main() {
+    let mut scripts_dir = PathBuf::from("resources");
+    let mut files = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--scripts" {
+            match args.next() {
+                Some(dir) => scripts_dir = PathBuf::from(dir),
+                None => {
+                    eprintln!("--scripts needs a directory");
+                    process::exit(2);
+                }
+            }
+        } else {
+            files.push(PathBuf::from(arg));
+        }
+    }
+    if files.is_empty() {
+        eprintln!("Usage: ld45-levelcheck [--scripts DIR] LEVEL.dat...");
+        process::exit(2);
+    }
+
+    let mut errors = 0;
+    let mut warnings = 0;
+    for path in files.iter() {
+        let report = check_level(path, &scripts_dir);
+        errors += report.errors;
+        warnings += report.warnings;
+    }
+    println!("{} errors, {} warnings", errors, warnings);
+    if errors != 0 {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crossing_segments_intersect() {
+        assert!(segments_intersect(
+            (0.0, 0.0),
+            (2.0, 2.0),
+            (0.0, 2.0),
+            (2.0, 0.0)
+        ));
+        assert!(!segments_intersect(
+            (0.0, 0.0),
+            (1.0, 1.0),
+            (0.0, 2.0),
+            (2.0, 2.0)
+        ));
+    }
+
+    #[test]
+    fn touching_and_collinear_segments_intersect() {
+        // An end point on the other segment
+        assert!(segments_intersect(
+            (0.0, 0.0),
+            (2.0, 0.0),
+            (1.0, 0.0),
+            (1.0, 5.0)
+        ));
+        // Overlapping on the same line
+        assert!(segments_intersect(
+            (0.0, 0.0),
+            (2.0, 0.0),
+            (1.0, 0.0),
+            (3.0, 0.0)
+        ));
+        // On the same line but apart
+        assert!(!segments_intersect(
+            (0.0, 0.0),
+            (1.0, 0.0),
+            (2.0, 0.0),
+            (3.0, 0.0)
+        ));
+    }
+
+    #[test]
+    fn self_intersecting_polygons() {
+        let square = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        assert!(!is_self_intersecting(&square));
+        let bow_tie = [(0.0, 0.0), (1.0, 1.0), (1.0, 0.0), (0.0, 1.0)];
+        assert!(is_self_intersecting(&bow_tie));
+    }
+
+    fn report() -> Report {
+        Report {
+            name: "test".to_string(),
+            errors: 0,
+            warnings: 0,
+        }
+    }
+
+    fn rect_trigger(id: u32, min_x: f32, max_x: f32, min_y: f32, max_y: f32) -> Trigger {
+        Trigger {
+            id,
+            min_x,
+            max_x,
+            min_y,
+            max_y,
+            shape: TriggerShape::Rectangle,
+            whole_ship: false,
+            priority: 0,
+        }
+    }
+
+    fn rect(min_x: f32, max_x: f32, min_y: f32, max_y: f32) -> Vec<(f32, f32)> {
+        vec![
+            (min_x, min_y),
+            (max_x, min_y),
+            (max_x, max_y),
+            (min_x, max_y),
+        ]
+    }
+
+    // The ship spawns on the left. Trigger 1 is across the bottom half of
+    // the middle and trigger 2 across the right.
+    fn level() -> RawMeshes {
+        RawMeshes {
+            polygons: Vec::new(),
+            triggers: vec![
+                rect_trigger(0, 0.0, 10.0, 0.0, 100.0),
+                rect_trigger(1, 40.0, 50.0, 0.0, 50.0),
+                rect_trigger(2, 80.0, 90.0, 0.0, 100.0),
+            ],
+            extents: Some(Extents {
+                min_x: 0.0,
+                max_x: 100.0,
+                min_y: 0.0,
+                max_y: 100.0,
+                collision_width: 100,
+                collision_height: 100,
+            }),
+            physics: Default::default(),
+            zones: Vec::new(),
+            wells: Vec::new(),
+            wind: Vec::new(),
+            obstacles: Vec::new(),
+            groups: Vec::new(),
+            pickups: Vec::new(),
+        }
+    }
+
+    fn count_sequence_warnings(meshes: &RawMeshes, source: &str) -> u32 {
+        let mut report = report();
+        check_sequence(&mut report, meshes, &Script::parse(source).unwrap());
+        report.warnings
+    }
+
+    #[test]
+    fn sequence_warns_about_way_around_trigger() {
+        let meshes = level();
+        assert_eq!(count_sequence_warnings(&meshes, "1 say A\n2 say B"), 1);
+        // Trigger 2 can't fire before trigger 1 anyway
+        assert_eq!(
+            count_sequence_warnings(&meshes, "1 say A\n2 say B\n2 when visited 1"),
+            0
+        );
+        // Repeating triggers aren't part of the story
+        assert_eq!(
+            count_sequence_warnings(&meshes, "1 say A\n2 say B\n1 repeat"),
+            0
+        );
+    }
+
+    #[test]
+    fn sequence_follows_walls() {
+        // A wall above trigger 1 closes the way around it
+        let mut meshes = level();
+        meshes.polygons.push(rect(40.0, 50.0, 48.0, 100.0));
+        assert_eq!(count_sequence_warnings(&meshes, "1 say A\n2 say B"), 0);
+        // Unless there is a gap at the top
+        meshes.polygons[0] = rect(40.0, 50.0, 48.0, 90.0);
+        assert_eq!(count_sequence_warnings(&meshes, "1 say A\n2 say B"), 1);
+    }
+
+    fn count_respawn_errors(meshes: &RawMeshes, source: &str) -> u32 {
+        let mut report = report();
+        check_respawn_points(&mut report, meshes, &Script::parse(source).unwrap());
+        report.errors
+    }
+
+    #[test]
+    fn respawn_points_must_not_be_in_walls() {
+        let mut meshes = level();
+        assert_eq!(count_respawn_errors(&meshes, "2 checkpoint"), 0);
+        // Trigger 1 isn't a checkpoint, so only the spawn point and
+        // trigger 2 count
+        meshes.polygons.push(rect(0.0, 100.0, 0.0, 60.0));
+        assert_eq!(count_respawn_errors(&meshes, "2 checkpoint"), 2);
+    }
+}
@@ -0,0 +1,214 @@
+// Collision map stored as tiles, so only the parts of the level that
+// contain walls need a bitmap.
+//
+// Pixel coordinates have (0, 0) in the top left corner of the level,
+// i.e. at minimum X and maximum Y.
+
+use bit_vec::BitVec;
+
+use ggez::graphics;
+use ggez::{conf, Context, GameResult};
+
+use ld45::meshes::Extents;
+
+// Width and height of a tile in pixels
+pub const TILE_SIZE: u32 = 64;
+
+// Width and height of the canvas used for rendering the level, in pixels.
+// Must be a multiple of TILE_SIZE.
+pub const CHUNK_SIZE: u32 = 1024;
+
+pub enum Tile {
+    Empty,
+    Full,
+    // TILE_SIZE * TILE_SIZE pixels, row by row
+    Mixed(BitVec),
+}
+
+pub struct CollisionMap {
+    width: u32,
+    height: u32,
+    tiles_x: u32,
+    tiles_y: u32,
+    tiles: Vec<Tile>,
+}
+
+impl CollisionMap {
+    pub fn new(width: u32, height: u32) -> CollisionMap {
+        let tiles_x = width.div_ceil(TILE_SIZE);
+        let tiles_y = height.div_ceil(TILE_SIZE);
+        CollisionMap {
+            width,
+            height,
+            tiles_x,
+            tiles_y,
+            tiles: (0..tiles_x * tiles_y).map(|_| Tile::Empty).collect(),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn tiles_x(&self) -> u32 {
+        self.tiles_x
+    }
+
+    pub fn tiles_y(&self) -> u32 {
+        self.tiles_y
+    }
+
+    pub fn get_tile(&self, tile_x: u32, tile_y: u32) -> &Tile {
+        &self.tiles[(tile_y * self.tiles_x + tile_x) as usize]
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> bool {
+        if x >= self.width || y >= self.height {
+            return false;
+        }
+        match self.get_tile(x / TILE_SIZE, y / TILE_SIZE) {
+            Tile::Empty => false,
+            Tile::Full => true,
+            Tile::Mixed(bits) => bits[((y % TILE_SIZE) * TILE_SIZE + x % TILE_SIZE) as usize],
+        }
+    }
+
+    // Set the contents of a tile. get_pixel is called with pixel coordinates
+    // within the tile. Pixels outside the map are always empty.
+    pub fn set_tile<F>(&mut self, tile_x: u32, tile_y: u32, get_pixel: F)
+    where
+        F: Fn(u32, u32) -> bool,
+    {
+        let x0 = tile_x * TILE_SIZE;
+        let y0 = tile_y * TILE_SIZE;
+        let bits = BitVec::from_fn((TILE_SIZE * TILE_SIZE) as usize, |i| {
+            let x = i as u32 % TILE_SIZE;
+            let y = i as u32 / TILE_SIZE;
+            x0 + x < self.width && y0 + y < self.height && get_pixel(x, y)
+        });
+        let tile = if bits.none() {
+            Tile::Empty
+        } else if bits.all() {
+            Tile::Full
+        } else {
+            Tile::Mixed(bits)
+        };
+        self.tiles[(tile_y * self.tiles_x + tile_x) as usize] = tile;
+    }
+
+    // Number of tiles that need a bitmap
+    pub fn get_mixed_tile_count(&self) -> usize {
+        self.tiles
+            .iter()
+            .filter(|t| matches!(t, Tile::Mixed(_)))
+            .count()
+    }
+}
+
+// The world coordinates of an area of the collision map given in pixels
+pub fn get_world_rect(extents: &Extents, x: u32, y: u32, w: u32, h: u32) -> graphics::Rect {
+    let pixel_width = (extents.max_x - extents.min_x) / extents.collision_width as f32;
+    let pixel_height = (extents.max_y - extents.min_y) / extents.collision_height as f32;
+    graphics::Rect::new(
+        extents.min_x + x as f32 * pixel_width,
+        extents.max_y - (y + h) as f32 * pixel_height,
+        w as f32 * pixel_width,
+        h as f32 * pixel_height,
+    )
+}
+
+// Render the area of the collision map in chunks the size of a canvas.
+// `draw` is called to draw each chunk in world coordinates, but only if
+// `is_empty` returns false for the chunk's world rectangle. Then `read` is
+// called with the pixel position of the chunk and its RGBA pixels,
+// CHUNK_SIZE * CHUNK_SIZE of them. Chunks come row by row from the top.
+pub fn render_in_chunks<E, D, R>(
+    ctx: &mut Context,
+    extents: &Extents,
+    is_empty: E,
+    mut draw: D,
+    mut read: R,
+) -> GameResult
+where
+    E: Fn(graphics::Rect) -> bool,
+    D: FnMut(&mut Context) -> GameResult,
+    R: FnMut(u32, u32, &[u8]) -> GameResult,
+{
+    let canvas = graphics::Canvas::new(
+        ctx,
+        CHUNK_SIZE as u16,
+        CHUNK_SIZE as u16,
+        conf::NumSamples::One,
+    )?;
+    for chunk_y in (0..extents.collision_height).step_by(CHUNK_SIZE as usize) {
+        for chunk_x in (0..extents.collision_width).step_by(CHUNK_SIZE as usize) {
+            let rect = get_world_rect(extents, chunk_x, chunk_y, CHUNK_SIZE, CHUNK_SIZE);
+            if is_empty(rect) {
+                continue;
+            }
+            graphics::set_canvas(ctx, Some(&canvas));
+            graphics::clear(ctx, [0.0, 0.0, 0.0, 0.0].into());
+            // The first row read back from the canvas is the one with the highest Y
+            graphics::set_screen_coordinates(ctx, rect)?;
+            draw(ctx)?;
+            graphics::present(ctx)?;
+            graphics::set_canvas(ctx, None);
+
+            let pixels = canvas.image().to_rgba8(ctx)?;
+            assert!(pixels.len() == (CHUNK_SIZE * CHUNK_SIZE * 4) as usize);
+            read(chunk_x, chunk_y, &pixels)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_are_folded_to_empty_full_or_mixed() {
+        // Two tiles wide, with the second one cut off by the edge of the map
+        let mut map = CollisionMap::new(TILE_SIZE + 10, TILE_SIZE);
+        assert_eq!((map.tiles_x(), map.tiles_y()), (2, 1));
+        assert!(matches!(map.get_tile(0, 0), Tile::Empty));
+
+        map.set_tile(0, 0, |_, _| true);
+        assert!(matches!(map.get_tile(0, 0), Tile::Full));
+        map.set_tile(0, 0, |_, _| false);
+        assert!(matches!(map.get_tile(0, 0), Tile::Empty));
+        map.set_tile(0, 0, |x, y| x == 3 && y == 5);
+        assert!(matches!(map.get_tile(0, 0), Tile::Mixed(_)));
+        assert_eq!(map.get_mixed_tile_count(), 1);
+        assert!(map.get(3, 5));
+        assert!(!map.get(5, 3));
+
+        // Pixels past the edge of the map stay empty, so the tile isn't full
+        map.set_tile(1, 0, |_, _| true);
+        assert!(matches!(map.get_tile(1, 0), Tile::Mixed(_)));
+        assert!(map.get(TILE_SIZE + 9, 0));
+        assert!(!map.get(TILE_SIZE + 10, 0));
+        assert_eq!(map.get_mixed_tile_count(), 2);
+    }
+
+    #[test]
+    fn world_rect_has_top_row_at_max_y() {
+        let extents = Extents {
+            min_x: -100.0,
+            max_x: 100.0,
+            min_y: 0.0,
+            max_y: 400.0,
+            collision_width: 200,
+            collision_height: 200,
+        };
+        let rect = get_world_rect(&extents, 0, 0, 10, 20);
+        assert_eq!(
+            (rect.x, rect.y, rect.w, rect.h),
+            (-100.0, 360.0, 10.0, 40.0)
+        );
+    }
+}
@@ -0,0 +1,224 @@
+// Debug overlay showing the collision map, the ship's collider points,
+// triggers, physics zones, gravity wells and the spawn point. Toggled with F3 in dev mode.
+
+use std::collections::hash_map::Entry;
+
+use bit_vec::BitVec;
+
+use ggez::graphics;
+use ggez::graphics::Color;
+use ggez::nalgebra::Point2;
+use ggez::{Context, GameResult};
+
+use crate::collision_map::{get_world_rect, Tile, TILE_SIZE};
+use crate::{world_to_ui, LevelState, Ship};
+
+const COLLISION_COLOR: [u8; 4] = [0xff, 0x00, 0xff, 0x60];
+const COLLIDER_COLOR: u32 = 0x00ff00;
+const COLLIDER_HIT_COLOR: u32 = 0xff0000;
+pub const TRIGGER_COLOR: u32 = 0xffff00;
+const SPAWN_COLOR: u32 = 0x00ffff;
+const ZONE_COLOR: u32 = 0x4080ff;
+const WELL_COLOR: u32 = 0xff8040;
+const TEXT_SIZE: f32 = 16.0;
+
+// Sizes in pixels
+const POINT_SIZE: f32 = 3.0;
+const SPAWN_SIZE: f32 = 10.0;
+
+fn create_tile_image(ctx: &mut Context, bits: &BitVec) -> GameResult<graphics::Image> {
+    let mut rgba = vec![0u8; bits.len() * 4];
+    for (i, bit) in bits.iter().enumerate() {
+        if bit {
+            rgba[i * 4..i * 4 + 4].copy_from_slice(&COLLISION_COLOR);
+        }
+    }
+    let mut image = graphics::Image::from_rgba8(ctx, TILE_SIZE as u16, TILE_SIZE as u16, &rgba)?;
+    image.set_filter(graphics::FilterMode::Nearest);
+    Ok(image)
+}
+
+// Draw the tiles of the collision map that are inside world_rect
+pub fn draw_collision_map(
+    ctx: &mut Context,
+    level: &mut LevelState,
+    world_rect: graphics::Rect,
+) -> GameResult {
+    let extents = level.extents;
+    let map = &level.collision_map;
+    if map.tiles_x() == 0 || map.tiles_y() == 0 {
+        return Ok(());
+    }
+    let tile_width = (extents.max_x - extents.min_x) * TILE_SIZE as f32 / map.width() as f32;
+    let tile_height = (extents.max_y - extents.min_y) * TILE_SIZE as f32 / map.height() as f32;
+    let top = world_rect.y.max(world_rect.y + world_rect.h);
+    let bottom = world_rect.y.min(world_rect.y + world_rect.h);
+    let to_tile = |value: f32, max: u32| (value.max(0.0) as u32).min(max - 1);
+    let first_x = to_tile((world_rect.x - extents.min_x) / tile_width, map.tiles_x());
+    let last_x = to_tile(
+        (world_rect.x + world_rect.w - extents.min_x) / tile_width,
+        map.tiles_x(),
+    );
+    // Tile row 0 is the top of the level, i.e. highest Y
+    let first_y = to_tile((extents.max_y - top) / tile_height, map.tiles_y());
+    let last_y = to_tile((extents.max_y - bottom) / tile_height, map.tiles_y());
+
+    let color = Color::from_rgba(
+        COLLISION_COLOR[0],
+        COLLISION_COLOR[1],
+        COLLISION_COLOR[2],
+        COLLISION_COLOR[3],
+    );
+    let fill = graphics::DrawMode::Fill(graphics::FillOptions::default());
+    let mut builder = graphics::MeshBuilder::new();
+    let mut mixed_tiles = Vec::new();
+    for tile_y in first_y..=last_y {
+        for tile_x in first_x..=last_x {
+            let rect = get_world_rect(
+                &extents,
+                tile_x * TILE_SIZE,
+                tile_y * TILE_SIZE,
+                TILE_SIZE,
+                TILE_SIZE,
+            );
+            match level.collision_map.get_tile(tile_x, tile_y) {
+                Tile::Empty => {}
+                Tile::Full => {
+                    builder.rectangle(fill, rect, color);
+                }
+                Tile::Mixed(bits) => {
+                    if let Entry::Vacant(entry) = level.collision_images.entry((tile_x, tile_y)) {
+                        entry.insert(create_tile_image(ctx, bits)?);
+                    }
+                    mixed_tiles.push(((tile_x, tile_y), rect));
+                }
+            }
+        }
+    }
+    if let Ok(mesh) = builder.build(ctx) {
+        graphics::draw(ctx, &mesh, graphics::DrawParam::default())?;
+    }
+    for (key, rect) in mixed_tiles {
+        // The first row of the tile image is the highest Y
+        graphics::draw(
+            ctx,
+            &level.collision_images[&key],
+            graphics::DrawParam::default()
+                .dest(Point2::new(rect.x, rect.y + rect.h))
+                .scale([rect.w / TILE_SIZE as f32, -rect.h / TILE_SIZE as f32]),
+        )?;
+    }
+    Ok(())
+}
+
+// Draw in world coordinates. pixel_size is the size of a screen pixel in world units.
+pub fn draw_world(
+    ctx: &mut Context,
+    level: &mut LevelState,
+    ship: &Ship,
+    world_rect: graphics::Rect,
+    pixel_size: f32,
+) -> GameResult {
+    draw_collision_map(ctx, level, world_rect)?;
+
+    let stroke =
+        graphics::DrawMode::Stroke(graphics::StrokeOptions::default().with_line_width(pixel_size));
+    let fill = graphics::DrawMode::Fill(graphics::FillOptions::default());
+    let mut builder = graphics::MeshBuilder::new();
+
+    for trigger in level.triggers.values() {
+        let points: Vec<Point2<f32>> = trigger
+            .get_outline()
+            .iter()
+            .map(|&(x, y)| Point2::new(x, y))
+            .collect();
+        if points.len() >= 3 {
+            builder.polygon(stroke, &points, Color::from_rgb_u32(TRIGGER_COLOR))?;
+        }
+    }
+
+    for zone in level.raw_meshes.zones.iter() {
+        let points: Vec<Point2<f32>> = zone
+            .polygon
+            .iter()
+            .map(|&(x, y)| Point2::new(x, y))
+            .collect();
+        if points.len() >= 3 {
+            builder.polygon(stroke, &points, Color::from_rgb_u32(ZONE_COLOR))?;
+        }
+    }
+
+    for well in level.raw_meshes.wells.iter() {
+        builder.circle(
+            stroke,
+            Point2::new(well.x, well.y),
+            well.radius,
+            pixel_size,
+            Color::from_rgb_u32(WELL_COLOR),
+        );
+    }
+
+    let spawn = level.spawn_position;
+    let size = SPAWN_SIZE * 0.5 * pixel_size;
+    let color = Color::from_rgb_u32(SPAWN_COLOR);
+    builder.line(
+        &[
+            Point2::new(spawn.x - size, spawn.y - size),
+            Point2::new(spawn.x + size, spawn.y + size),
+        ],
+        pixel_size,
+        color,
+    )?;
+    builder.line(
+        &[
+            Point2::new(spawn.x - size, spawn.y + size),
+            Point2::new(spawn.x + size, spawn.y - size),
+        ],
+        pixel_size,
+        color,
+    )?;
+
+    if ship.alive {
+        let size = POINT_SIZE * pixel_size;
+        for point in ship.get_collider_points() {
+            let color = if level.get_collision(point) {
+                COLLIDER_HIT_COLOR
+            } else {
+                COLLIDER_COLOR
+            };
+            builder.rectangle(
+                fill,
+                graphics::Rect::new(point.x - size * 0.5, point.y - size * 0.5, size, size),
+                Color::from_rgb_u32(color),
+            );
+        }
+    }
+
+    // Building fails if nothing was added
+    if let Ok(mesh) = builder.build(ctx) {
+        graphics::draw(ctx, &mesh, graphics::DrawParam::default())?;
+    }
+    Ok(())
+}
+
+// Draw trigger IDs in UI coordinates
+pub fn draw_ui(
+    ctx: &mut Context,
+    level: &LevelState,
+    font: graphics::Font,
+    world_rect: graphics::Rect,
+    ui_rect: graphics::Rect,
+) -> GameResult {
+    let param = graphics::DrawParam::default().color(Color::from_rgb_u32(TRIGGER_COLOR));
+    for trigger in level.triggers.values() {
+        let mut text = graphics::Text::new(format!("{}", trigger.id));
+        text.set_font(font, graphics::Scale::uniform(TEXT_SIZE));
+        let position = world_to_ui(
+            Point2::new(trigger.min_x, trigger.max_y),
+            world_rect,
+            ui_rect,
+        );
+        graphics::draw(ctx, &text, param.dest(position))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,510 @@
+// In-game level editor. Only available in dev mode.
+//
+// F2: Toggle editor
+// Arrow keys/WASD: Scroll
+// Mouse wheel: Zoom
+// Left mouse button: Select and drag vertex, trigger corner or trigger
+// V/Insert: Add a vertex after the selected one
+// Delete/Backspace: Delete selected vertex or trigger
+// N: New polygon
+// T: New trigger
+// Page Up/Page Down: Change ID of selected trigger
+// Ctrl+S: Save
+
+use std::fs;
+use std::path::PathBuf;
+
+use ggez::graphics;
+use ggez::graphics::Color;
+use ggez::input;
+use ggez::input::keyboard::{KeyCode, KeyMods};
+use ggez::input::mouse::MouseButton;
+use ggez::nalgebra::{Point2, Vector2};
+use ggez::{Context, GameError, GameResult};
+
+use ld45::meshes::{Trigger, TriggerShape};
+
+use crate::{get_draw_rects, world_to_ui, LevelState, VISIBLE_HEIGHT};
+
+const SCROLL_SPEED: f32 = 400.0;
+const ZOOM_STEP: f32 = 1.2;
+const MIN_VISIBLE_HEIGHT: f32 = 50.0;
+const MAX_VISIBLE_HEIGHT: f32 = 3000.0;
+
+// Size of vertex handles and how close to click, in pixels
+const HANDLE_SIZE: f32 = 6.0;
+const PICK_DISTANCE: f32 = 8.0;
+
+const HANDLE_COLOR: u32 = 0xffffff;
+const SELECTED_COLOR: u32 = 0xff4040;
+const TRIGGER_COLOR: u32 = 0xe0c040;
+const TEXT_COLOR: u32 = 0xffffff;
+const TEXT_SIZE: f32 = 16.0;
+
+// Size of new polygons and triggers
+const NEW_OBJECT_SIZE: f32 = 20.0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Selection {
+    Vertex { polygon: usize, vertex: usize },
+    // Corners are numbered counter-clockwise starting at min_x, min_y.
+    // No corner means the whole trigger.
+    Trigger { index: usize, corner: Option<usize> },
+}
+
+pub struct Editor {
+    pub active: bool,
+    pub camera: Point2<f32>,
+    pub visible_height: f32,
+    // Where to find and save level files
+    dirs: Vec<PathBuf>,
+    selection: Option<Selection>,
+    // World position of the mouse when dragging
+    drag_position: Option<Point2<f32>>,
+    // Geometry changed, so the collision map is out of date
+    geometry_changed: bool,
+    unsaved: bool,
+}
+
+impl Editor {
+    pub fn new(dirs: Vec<PathBuf>) -> Editor {
+        Editor {
+            active: false,
+            camera: Point2::new(0.0, 0.0),
+            visible_height: VISIBLE_HEIGHT,
+            dirs,
+            selection: None,
+            drag_position: None,
+            geometry_changed: false,
+            unsaved: false,
+        }
+    }
+
+    pub fn toggle(&mut self, camera: Point2<f32>) {
+        self.active = !self.active;
+        if self.active {
+            self.camera = camera;
+        }
+        self.selection = None;
+        self.drag_position = None;
+    }
+
+    pub fn update(&mut self, ctx: &mut Context) {
+        let mut direction = Vector2::new(0.0, 0.0);
+        let pressed = |a, b| {
+            input::keyboard::is_key_pressed(ctx, a) || input::keyboard::is_key_pressed(ctx, b)
+        };
+        if pressed(KeyCode::Left, KeyCode::A) {
+            direction.x -= 1.0;
+        }
+        if pressed(KeyCode::Right, KeyCode::D) {
+            direction.x += 1.0;
+        }
+        if pressed(KeyCode::Up, KeyCode::W) {
+            direction.y += 1.0;
+        }
+        if pressed(KeyCode::Down, KeyCode::S) {
+            direction.y -= 1.0;
+        }
+        let seconds = ggez::timer::duration_to_f64(ggez::timer::delta(ctx)) as f32;
+        self.camera += direction * SCROLL_SPEED * seconds * self.visible_height / VISIBLE_HEIGHT;
+    }
+
+    // Size of a screen pixel in world units
+    fn pixel_size(&self, ctx: &Context) -> f32 {
+        let (_, win_height) = graphics::drawable_size(ctx);
+        self.visible_height / win_height.max(1.0)
+    }
+
+    fn screen_to_world(&self, ctx: &Context, x: f32, y: f32) -> Point2<f32> {
+        let (world_rect, _) = get_draw_rects(ctx, self.camera, self.visible_height);
+        let (win_width, win_height) = graphics::drawable_size(ctx);
+        Point2::new(
+            world_rect.x + x / win_width * world_rect.w,
+            world_rect.y + y / win_height * world_rect.h,
+        )
+    }
+
+    fn pick(&self, ctx: &Context, level: &LevelState, point: Point2<f32>) -> Option<Selection> {
+        let max_distance = PICK_DISTANCE * self.pixel_size(ctx);
+        let is_near = |x: f32, y: f32| {
+            (x - point.x).abs() <= max_distance && (y - point.y).abs() <= max_distance
+        };
+
+        for (polygon_index, polygon) in level.raw_meshes.polygons.iter().enumerate() {
+            for (vertex_index, &(x, y)) in polygon.iter().enumerate() {
+                if is_near(x, y) {
+                    return Some(Selection::Vertex {
+                        polygon: polygon_index,
+                        vertex: vertex_index,
+                    });
+                }
+            }
+        }
+        for (index, trigger) in level.raw_meshes.triggers.iter().enumerate() {
+            for (corner, (x, y)) in trigger_corners(trigger).iter().enumerate() {
+                if is_near(*x, *y) {
+                    return Some(Selection::Trigger {
+                        index,
+                        corner: Some(corner),
+                    });
+                }
+            }
+        }
+        for (index, trigger) in level.raw_meshes.triggers.iter().enumerate() {
+            if trigger.min_x <= point.x
+                && point.x < trigger.max_x
+                && trigger.min_y <= point.y
+                && point.y < trigger.max_y
+            {
+                return Some(Selection::Trigger {
+                    index,
+                    corner: None,
+                });
+            }
+        }
+        None
+    }
+
+    pub fn mouse_wheel(&mut self, y: f32) {
+        self.visible_height = (self.visible_height * ZOOM_STEP.powf(-y))
+            .clamp(MIN_VISIBLE_HEIGHT, MAX_VISIBLE_HEIGHT);
+    }
+
+    pub fn mouse_button_down(
+        &mut self,
+        ctx: &mut Context,
+        level: &LevelState,
+        button: MouseButton,
+        x: f32,
+        y: f32,
+    ) {
+        if button != MouseButton::Left {
+            return;
+        }
+        let position = self.screen_to_world(ctx, x, y);
+        self.selection = self.pick(ctx, level, position);
+        if self.selection.is_some() {
+            self.drag_position = Some(position);
+        }
+    }
+
+    pub fn mouse_motion(
+        &mut self,
+        ctx: &mut Context,
+        level: &mut LevelState,
+        x: f32,
+        y: f32,
+    ) -> GameResult {
+        let (selection, previous) = match (self.selection, self.drag_position) {
+            (Some(s), Some(p)) => (s, p),
+            _ => return Ok(()),
+        };
+        let position = self.screen_to_world(ctx, x, y);
+        let delta = position - previous;
+        self.drag_position = Some(position);
+
+        match selection {
+            Selection::Vertex { polygon, vertex } => {
+                let v = &mut level.raw_meshes.polygons[polygon][vertex];
+                v.0 += delta.x;
+                v.1 += delta.y;
+                self.geometry_changed = true;
+            }
+            Selection::Trigger { index, corner } => {
+                let t = &mut level.raw_meshes.triggers[index];
+                match corner {
+                    None => {
+                        t.min_x += delta.x;
+                        t.max_x += delta.x;
+                        t.min_y += delta.y;
+                        t.max_y += delta.y;
+                    }
+                    Some(corner) => {
+                        if corner == 0 || corner == 3 {
+                            t.min_x += delta.x;
+                        } else {
+                            t.max_x += delta.x;
+                        }
+                        if corner == 0 || corner == 1 {
+                            t.min_y += delta.y;
+                        } else {
+                            t.max_y += delta.y;
+                        }
+                    }
+                }
+            }
+        }
+        self.unsaved = true;
+        level.update_meshes(ctx)
+    }
+
+    pub fn mouse_button_up(
+        &mut self,
+        ctx: &mut Context,
+        level: &mut LevelState,
+        button: MouseButton,
+    ) -> GameResult {
+        if button != MouseButton::Left {
+            return Ok(());
+        }
+        self.drag_position = None;
+
+        if let Some(Selection::Trigger { index, .. }) = self.selection {
+            // Corners may have been dragged past each other
+            let t = &mut level.raw_meshes.triggers[index];
+            let flip_x = t.min_x > t.max_x;
+            let flip_y = t.min_y > t.max_y;
+            if flip_x {
+                std::mem::swap(&mut t.min_x, &mut t.max_x);
+            }
+            if flip_y {
+                std::mem::swap(&mut t.min_y, &mut t.max_y);
+            }
+            // Keep the polygon where it was drawn
+            if let TriggerShape::Polygon(polygon) = &mut t.shape {
+                for (u, v) in polygon.iter_mut() {
+                    if flip_x {
+                        *u = 1.0 - *u;
+                    }
+                    if flip_y {
+                        *v = 1.0 - *v;
+                    }
+                }
+            }
+            level.update_meshes(ctx)?;
+        }
+        self.update_collision_map(ctx, level)
+    }
+
+    fn update_collision_map(&mut self, ctx: &mut Context, level: &mut LevelState) -> GameResult {
+        if self.geometry_changed {
+            self.geometry_changed = false;
+            level.update_collision_map(ctx)?;
+        }
+        Ok(())
+    }
+
+    pub fn key_down(
+        &mut self,
+        ctx: &mut Context,
+        level: &mut LevelState,
+        keycode: KeyCode,
+    ) -> GameResult {
+        let mouse_position = input::mouse::position(ctx);
+        let mouse_position = self.screen_to_world(ctx, mouse_position.x, mouse_position.y);
+        let meshes = &mut level.raw_meshes;
+
+        match (keycode, self.selection) {
+            (KeyCode::V, Some(Selection::Vertex { polygon, vertex }))
+            | (KeyCode::Insert, Some(Selection::Vertex { polygon, vertex })) => {
+                meshes.polygons[polygon].insert(vertex + 1, (mouse_position.x, mouse_position.y));
+                self.selection = Some(Selection::Vertex {
+                    polygon,
+                    vertex: vertex + 1,
+                });
+                self.geometry_changed = true;
+            }
+            (KeyCode::Delete, Some(Selection::Vertex { polygon, vertex }))
+            | (KeyCode::Back, Some(Selection::Vertex { polygon, vertex })) => {
+                meshes.polygons[polygon].remove(vertex);
+                if meshes.polygons[polygon].len() < 3 {
+                    meshes.polygons.remove(polygon);
+                }
+                self.selection = None;
+                self.geometry_changed = true;
+            }
+            (KeyCode::Delete, Some(Selection::Trigger { index, .. }))
+            | (KeyCode::Back, Some(Selection::Trigger { index, .. })) => {
+                meshes.triggers.remove(index);
+                self.selection = None;
+            }
+            (KeyCode::N, _) => {
+                let (x, y) = (mouse_position.x, mouse_position.y);
+                let size = NEW_OBJECT_SIZE * 0.5;
+                meshes.polygons.push(vec![
+                    (x - size, y - size),
+                    (x + size, y - size),
+                    (x, y + size),
+                ]);
+                self.selection = Some(Selection::Vertex {
+                    polygon: meshes.polygons.len() - 1,
+                    vertex: 2,
+                });
+                self.geometry_changed = true;
+            }
+            (KeyCode::T, _) => {
+                let id = meshes.triggers.iter().map(|t| t.id + 1).max().unwrap_or(0);
+                let size = NEW_OBJECT_SIZE * 0.5;
+                meshes.triggers.push(Trigger {
+                    id,
+                    min_x: mouse_position.x - size,
+                    max_x: mouse_position.x + size,
+                    min_y: mouse_position.y - size,
+                    max_y: mouse_position.y + size,
+                    shape: TriggerShape::Rectangle,
+                    whole_ship: false,
+                    priority: 0,
+                });
+                self.selection = Some(Selection::Trigger {
+                    index: meshes.triggers.len() - 1,
+                    corner: None,
+                });
+            }
+            (KeyCode::PageUp, Some(Selection::Trigger { index, .. })) => {
+                meshes.triggers[index].id += 1;
+            }
+            (KeyCode::PageDown, Some(Selection::Trigger { index, .. })) => {
+                let t = &mut meshes.triggers[index];
+                t.id = t.id.saturating_sub(1);
+            }
+            _ => return Ok(()),
+        }
+        self.unsaved = true;
+        level.update_meshes(ctx)?;
+        self.update_collision_map(ctx, level)
+    }
+
+    pub fn is_save_key(keycode: KeyCode, keymods: KeyMods) -> bool {
+        keycode == KeyCode::S && keymods.contains(KeyMods::CTRL)
+    }
+
+    // Write the level back to the file it was loaded from
+    pub fn save(&mut self, level: &LevelState) -> GameResult {
+        let file_name = format!("level{:02}.dat", level.level_number);
+        let path = self
+            .dirs
+            .iter()
+            .map(|dir| dir.join(&file_name))
+            .find(|path| path.exists())
+            .or_else(|| self.dirs.last().map(|dir| dir.join(&file_name)))
+            .ok_or_else(|| GameError::FilesystemError("No level directory".to_string()))?;
+        let encoded = level.raw_meshes.encode()?;
+        fs::write(&path, encoded)?;
+        println!("Saved {}", path.display());
+        self.unsaved = false;
+        Ok(())
+    }
+
+    // Draw handles in world coordinates
+    pub fn draw_world(&self, ctx: &mut Context, level: &LevelState) -> GameResult {
+        let pixel_size = self.pixel_size(ctx);
+        let stroke = graphics::DrawMode::Stroke(
+            graphics::StrokeOptions::default().with_line_width(pixel_size),
+        );
+        let handle_size = HANDLE_SIZE * pixel_size;
+        let handle_rect = |x: f32, y: f32| {
+            graphics::Rect::new(
+                x - handle_size * 0.5,
+                y - handle_size * 0.5,
+                handle_size,
+                handle_size,
+            )
+        };
+        let color = |selected: bool| {
+            Color::from_rgb_u32(if selected {
+                SELECTED_COLOR
+            } else {
+                HANDLE_COLOR
+            })
+        };
+
+        let mut builder = graphics::MeshBuilder::new();
+        for (polygon_index, polygon) in level.raw_meshes.polygons.iter().enumerate() {
+            for (vertex_index, &(x, y)) in polygon.iter().enumerate() {
+                let selected = self.selection
+                    == Some(Selection::Vertex {
+                        polygon: polygon_index,
+                        vertex: vertex_index,
+                    });
+                builder.rectangle(stroke, handle_rect(x, y), color(selected));
+            }
+        }
+        for (index, trigger) in level.raw_meshes.triggers.iter().enumerate() {
+            let selected = match self.selection {
+                Some(Selection::Trigger { index: i, .. }) => i == index,
+                _ => false,
+            };
+            builder.rectangle(
+                stroke,
+                graphics::Rect::new(
+                    trigger.min_x,
+                    trigger.min_y,
+                    trigger.max_x - trigger.min_x,
+                    trigger.max_y - trigger.min_y,
+                ),
+                if selected {
+                    Color::from_rgb_u32(SELECTED_COLOR)
+                } else {
+                    Color::from_rgb_u32(TRIGGER_COLOR)
+                },
+            );
+            if trigger.shape != TriggerShape::Rectangle {
+                let points: Vec<Point2<f32>> = trigger
+                    .get_outline()
+                    .iter()
+                    .map(|&(x, y)| Point2::new(x, y))
+                    .collect();
+                if points.len() >= 3 {
+                    builder.polygon(stroke, &points, Color::from_rgb_u32(TRIGGER_COLOR))?;
+                }
+            }
+            for (x, y) in trigger_corners(trigger).iter() {
+                builder.rectangle(stroke, handle_rect(*x, *y), color(selected));
+            }
+        }
+        if level.raw_meshes.polygons.is_empty() && level.raw_meshes.triggers.is_empty() {
+            return Ok(());
+        }
+        let mesh = builder.build(ctx)?;
+        graphics::draw(ctx, &mesh, graphics::DrawParam::default())
+    }
+
+    // Draw labels and status in UI coordinates
+    pub fn draw_ui(
+        &self,
+        ctx: &mut Context,
+        level: &LevelState,
+        font: graphics::Font,
+        ui_rect: graphics::Rect,
+    ) -> GameResult {
+        let (world_rect, _) = get_draw_rects(ctx, self.camera, self.visible_height);
+        let scale = graphics::Scale::uniform(TEXT_SIZE);
+        let text_param = graphics::DrawParam::default().color(Color::from_rgb_u32(TEXT_COLOR));
+
+        for trigger in level.raw_meshes.triggers.iter() {
+            let mut text = graphics::Text::new(format!("{}", trigger.id));
+            text.set_font(font, scale);
+            let position = world_to_ui(
+                Point2::new(trigger.min_x, trigger.max_y),
+                world_rect,
+                ui_rect,
+            );
+            graphics::draw(
+                ctx,
+                &text,
+                text_param
+                    .dest(position)
+                    .color(Color::from_rgb_u32(TRIGGER_COLOR)),
+            )?;
+        }
+
+        let mut status = graphics::Text::new(format!(
+            "EDITOR  level {}{}",
+            level.level_number,
+            if self.unsaved { "  (unsaved)" } else { "" }
+        ));
+        status.set_font(font, scale);
+        let position = Point2::new(0.0, ui_rect.h - TEXT_SIZE);
+        graphics::draw(ctx, &status, text_param.dest(position))
+    }
+}
+
+fn trigger_corners(trigger: &Trigger) -> [(f32, f32); 4] {
+    [
+        (trigger.min_x, trigger.min_y),
+        (trigger.max_x, trigger.min_y),
+        (trigger.max_x, trigger.max_y),
+        (trigger.min_x, trigger.max_y),
+    ]
+}
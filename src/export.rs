@@ -0,0 +1,203 @@
+// Export of the collision map and a render of the whole level to PNG files,
+// so levels can be reviewed and compared without running the game.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use deflate::write::ZlibEncoder;
+use ggez::graphics;
+use ggez::graphics::Color;
+use ggez::nalgebra::Point2;
+use ggez::{Context, GameResult};
+
+use crate::collision_map::{render_in_chunks, CHUNK_SIZE};
+use crate::debug_overlay::TRIGGER_COLOR;
+use crate::{LevelState, BACKGROUND_COLOR};
+
+const TEXT_SIZE: f32 = 16.0;
+// Size of the compressed image data in each PNG chunk
+const IDAT_SIZE: usize = 64 * 1024;
+
+fn png_error(e: png::EncodingError) -> io::Error {
+    match e {
+        png::EncodingError::IoError(e) => e,
+        e => io::Error::other(e.to_string()),
+    }
+}
+
+// Writes compressed image data as PNG chunks
+struct IdatWriter(png::Writer<File>);
+
+impl Write for IdatWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .write_chunk(png::chunk::IDAT, buf)
+            .map_err(png_error)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Writes a PNG file one row at a time from the top, so big levels don't need
+// the whole image in memory
+struct PngWriter {
+    encoder: ZlibEncoder<BufWriter<IdatWriter>>,
+}
+
+impl PngWriter {
+    fn create(
+        path: &Path,
+        width: u32,
+        height: u32,
+        color: png::ColorType,
+    ) -> GameResult<PngWriter> {
+        let mut encoder = png::Encoder::new(File::create(path)?, width, height);
+        encoder.set_color(color);
+        encoder.set_depth(png::BitDepth::Eight);
+        let writer = encoder.write_header().map_err(png_error)?;
+        Ok(PngWriter {
+            encoder: ZlibEncoder::new(
+                BufWriter::with_capacity(IDAT_SIZE, IdatWriter(writer)),
+                deflate::Compression::Default,
+            ),
+        })
+    }
+
+    fn write_row(&mut self, row: &[u8]) -> GameResult {
+        // No filter
+        self.encoder.write_all(&[0])?;
+        self.encoder.write_all(row)?;
+        Ok(())
+    }
+
+    // Dropping the PNG writer writes the end of the file
+    fn finish(self) -> GameResult {
+        self.encoder
+            .finish()?
+            .into_inner()
+            .map_err(io::Error::from)?;
+        Ok(())
+    }
+}
+
+pub fn export_level_images(
+    ctx: &mut Context,
+    level: &LevelState,
+    font: graphics::Font,
+    dir: &Path,
+) -> GameResult {
+    std::fs::create_dir_all(dir)?;
+
+    let path = dir.join(format!("level{:02}-collision.png", level.level_number));
+    export_collision_map(level, &path)?;
+    println!("Wrote {}", path.display());
+
+    let path = dir.join(format!("level{:02}-render.png", level.level_number));
+    export_render(ctx, level, font, &path)?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
+
+// Write the collision map with colliding pixels in white
+fn export_collision_map(level: &LevelState, path: &Path) -> GameResult {
+    let map = &level.collision_map;
+    let mut writer = PngWriter::create(path, map.width(), map.height(), png::ColorType::Grayscale)?;
+    let mut row = vec![0u8; map.width() as usize];
+    for y in 0..map.height() {
+        for x in 0..map.width() {
+            row[x as usize] = if map.get(x, y) { 0xff } else { 0x00 };
+        }
+        writer.write_row(&row)?;
+    }
+    writer.finish()
+}
+
+// Render the level with triggers, with the same resolution as the collision map
+fn export_render(
+    ctx: &mut Context,
+    level: &LevelState,
+    font: graphics::Font,
+    path: &Path,
+) -> GameResult {
+    let extents = level.extents;
+    let width = extents.collision_width;
+    let height = extents.collision_height;
+    let pixel_size = (extents.max_x - extents.min_x) / width as f32;
+    let trigger_color = Color::from_rgb_u32(TRIGGER_COLOR);
+    let mut writer = PngWriter::create(path, width, height, png::ColorType::RGBA)?;
+    // One row of chunks at a time
+    let row_length = width as usize * 4;
+    let mut band = vec![0u8; CHUNK_SIZE as usize * row_length];
+
+    // The level may be bigger than a canvas, so it is rendered in chunks.
+    // They use the same coordinates as when rendering the collision map, so
+    // the image comes out the same way up as the collision map.
+    render_in_chunks(
+        ctx,
+        &extents,
+        |_| false,
+        |ctx| {
+            graphics::clear(ctx, Color::from_rgb_u32(BACKGROUND_COLOR));
+            let draw_param = graphics::DrawParam::default();
+            level.draw_walls(ctx)?;
+            level.draw_obstacles(ctx)?;
+            if !level.triggers.is_empty() {
+                let mut builder = graphics::MeshBuilder::new();
+                for trigger in level.triggers.values() {
+                    let points: Vec<Point2<f32>> = trigger
+                        .get_outline()
+                        .iter()
+                        .map(|&(x, y)| Point2::new(x, y))
+                        .collect();
+                    if points.len() >= 3 {
+                        builder.polygon(
+                            graphics::DrawMode::Stroke(
+                                graphics::StrokeOptions::default().with_line_width(pixel_size),
+                            ),
+                            &points,
+                            trigger_color,
+                        )?;
+                    }
+                }
+                let mesh = builder.build(ctx)?;
+                graphics::draw(ctx, &mesh, draw_param)?;
+            }
+            for trigger in level.triggers.values() {
+                let mut text = graphics::Text::new(format!("{}", trigger.id));
+                text.set_font(font, graphics::Scale::uniform(TEXT_SIZE));
+                // Negative Y scale because the image gets flipped when read back
+                graphics::draw(
+                    ctx,
+                    &text,
+                    draw_param
+                        .dest(Point2::new(trigger.min_x, trigger.max_y))
+                        .scale([pixel_size, -pixel_size])
+                        .color(trigger_color),
+                )?;
+            }
+            Ok(())
+        },
+        |chunk_x, chunk_y, chunk_pixels| {
+            let chunk_row_length = (CHUNK_SIZE.min(width - chunk_x) * 4) as usize;
+            let rows = CHUNK_SIZE.min(height - chunk_y) as usize;
+            for y in 0..rows {
+                let src = y * CHUNK_SIZE as usize * 4;
+                let dst = y * row_length + chunk_x as usize * 4;
+                band[dst..dst + chunk_row_length]
+                    .copy_from_slice(&chunk_pixels[src..src + chunk_row_length]);
+            }
+            // Every chunk is rendered, so the band is complete after the last
+            if chunk_x + CHUNK_SIZE >= width {
+                for row in band.chunks(row_length).take(rows) {
+                    writer.write_row(row)?;
+                }
+            }
+            Ok(())
+        },
+    )?;
+    writer.finish()
+}
@@ -0,0 +1,66 @@
+// Watches the files of the current level so they can be reloaded
+// while the game is running. Only used in dev mode.
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+pub struct LevelWatcher {
+    // Directories to look for level files in, i.e. the resource paths
+    dirs: Vec<PathBuf>,
+    level_number: u32,
+    modified: Vec<Option<SystemTime>>,
+    last_poll: Instant,
+}
+
+impl LevelWatcher {
+    pub fn new(dirs: Vec<PathBuf>, level_number: u32) -> LevelWatcher {
+        let mut watcher = LevelWatcher {
+            dirs,
+            level_number,
+            modified: Vec::new(),
+            last_poll: Instant::now(),
+        };
+        watcher.modified = watcher.get_modification_times();
+        watcher
+    }
+
+    fn get_modification_times(&self) -> Vec<Option<SystemTime>> {
+        let file_names = [
+            format!("level{:02}.dat", self.level_number),
+            format!("level{:02}.script", self.level_number),
+        ];
+        self.dirs
+            .iter()
+            .flat_map(|dir| file_names.iter().map(move |name| dir.join(name)))
+            .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+
+    // Forget about any changes, e.g. after the files were written by the game itself.
+    pub fn reset(&mut self, level_number: u32) {
+        self.level_number = level_number;
+        self.modified = self.get_modification_times();
+    }
+
+    // Returns true if any of the level's files have changed since last time.
+    pub fn poll(&mut self, level_number: u32) -> bool {
+        if level_number != self.level_number {
+            self.reset(level_number);
+            return false;
+        }
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+        let modified = self.get_modification_times();
+        if modified != self.modified {
+            self.modified = modified;
+            true
+        } else {
+            false
+        }
+    }
+}
@@ -0,0 +1,4 @@
+// Code shared between the game and the tools in src/bin
+
+pub mod meshes;
+pub mod script;
@@ -1,9 +1,37 @@
 #![windows_subsystem = "windows"]
 
-use serde::Deserialize;
+mod collision_map;
+mod debug_overlay;
+mod editor;
+mod export;
+mod hot_reload;
+mod messages;
+mod music;
+mod progress;
+mod settings;
+mod ship_audio;
+mod streaming;
+mod strings;
+mod subtitles;
+mod wind;
+
+use collision_map::{render_in_chunks, CollisionMap, Tile, CHUNK_SIZE, TILE_SIZE};
+use editor::Editor;
+use hot_reload::LevelWatcher;
+use ld45::meshes::{Extents, Physics, RawMeshes, Trigger};
+use ld45::script::{Ability, Action, Script};
+use messages::Messages;
+use music::{Music, DEFAULT_CROSSFADE, DEFAULT_TRACK};
+use progress::{Checkpoint, Progress};
+use settings::{Channel, Settings, SettingsMenu};
+use ship_audio::{ShipAudio, WARNING_DISTANCE};
+use streaming::{SectionData, SectionState, Streamer, UNLOAD_DISTANCE};
+use strings::{Strings, DEFAULT_LANGUAGE};
+use subtitles::Subtitles;
+use wind::WindParticles;
+
 use std::io::Read;
 
-use bit_vec::BitVec;
 use std::collections::{HashMap, HashSet};
 
 //use cgmath;
@@ -11,7 +39,6 @@ use ggez::nalgebra;
 use ggez::nalgebra::Point2;
 use ggez::nalgebra::Vector2;
 
-use ggez;
 use ggez::audio;
 use ggez::audio::SoundSource;
 use ggez::conf;
@@ -20,9 +47,10 @@ use ggez::filesystem::File;
 use ggez::graphics;
 use ggez::graphics::Color;
 use ggez::input;
-use ggez::input::keyboard::KeyCode;
+use ggez::input::keyboard::{KeyCode, KeyMods};
+use ggez::input::mouse::MouseButton;
 use ggez::timer;
-use ggez::{Context, GameResult};
+use ggez::{Context, GameError, GameResult};
 
 use std::env;
 use std::path;
@@ -32,6 +60,7 @@ const DEAD_TIMEOUT: f32 = 1.5;
 const TICKS_PER_SECOND: u32 = 60;
 const TICK_TIME: f32 = 1.0 / TICKS_PER_SECOND as f32;
 
+// Defaults for levels that don't override them
 const TURN_SPEED: f32 = 2.5;
 const THRUST: f32 = 120.0;
 const GRAVITY: f32 = 40.0;
@@ -47,17 +76,39 @@ const FILL_COLOR: u32 = 0x000000;
 const WALL_COLOR: u32 = 0x2ca693;
 const BACKGROUND_COLOR: u32 = 0x023f3c;
 
-const FONT_SIZE: f32 = 28.0;
+const HUD_FONT_SIZE: f32 = 20.0;
 
-const LEVEL_EXTENTS: graphics::Rect = graphics::Rect {
-    x: -500.0,
-    y: -1000.0,
-    w: 1000.0,
-    h: 2000.0,
-};
+const PICKUP_COLOR: u32 = 0xffe080;
 
-const COLLISION_MAP_WIDTH: u32 = 1024;
-const COLLISION_MAP_HEIGHT: u32 = 2048;
+// Physics constants of a level, with the level's overrides applied
+#[derive(Debug, Copy, Clone)]
+struct LevelPhysics {
+    gravity: f32,
+    thrust: f32,
+    turn_speed: f32,
+    energy_conservation: f32,
+}
+
+impl LevelPhysics {
+    fn new(overrides: &Physics) -> LevelPhysics {
+        LevelPhysics {
+            gravity: overrides.gravity.unwrap_or(GRAVITY),
+            thrust: overrides.thrust.unwrap_or(THRUST),
+            turn_speed: overrides.turn_speed.unwrap_or(TURN_SPEED),
+            energy_conservation: overrides.energy_conservation.unwrap_or(ENERGY_CONSERVATION),
+        }
+    }
+}
+
+// The physics at the ship's position, i.e. including zones
+#[derive(Debug, Copy, Clone)]
+struct Environment {
+    gravity: Vector2<f32>,
+    wind: Vector2<f32>,
+    energy_conservation: f32,
+    drag: f32,
+    thrust_multiplier: f32,
+}
 
 struct Ship {
     position: Point2<f32>,
@@ -77,17 +128,17 @@ struct Ship {
 }
 
 impl Ship {
-    fn reset(&mut self, position: Point2<f32>) {
+    fn reset(&mut self, position: Point2<f32>, angle: f32) {
         self.position = position;
         self.velocity = Vector2::new(0.0, 0.0);
         self.angular_velocity = 0.0;
         self.thrust = 0.0;
-        self.angle = std::f32::consts::FRAC_PI_2;
+        self.angle = angle;
         self.alive = true;
         self.dead_time = 0.0;
     }
 
-    fn update(&mut self, ctx: &mut Context) -> GameResult {
+    fn update(&mut self, ctx: &mut Context, physics: &LevelPhysics) -> GameResult {
         self.angular_velocity = 0.0;
         self.thrust = 0.0;
 
@@ -96,35 +147,49 @@ impl Ship {
                 && (input::keyboard::is_key_pressed(ctx, KeyCode::A)
                     || input::keyboard::is_key_pressed(ctx, KeyCode::Left))
             {
-                self.angular_velocity += TURN_SPEED;
+                self.angular_velocity += physics.turn_speed;
             }
             if self.turning_enabled
                 && (input::keyboard::is_key_pressed(ctx, KeyCode::D)
                     || input::keyboard::is_key_pressed(ctx, KeyCode::Right))
             {
-                self.angular_velocity -= TURN_SPEED;
+                self.angular_velocity -= physics.turn_speed;
             }
             if self.thrust_enabled
                 && (input::keyboard::is_key_pressed(ctx, KeyCode::W)
                     || input::keyboard::is_key_pressed(ctx, KeyCode::Up))
             {
-                self.thrust = THRUST;
+                self.thrust = physics.thrust;
             }
         }
         Ok(())
     }
 
-    fn tick(&mut self, _ctx: &mut Context) -> GameResult {
+    // Get the points of the collider polygons in world coordinates
+    fn get_collider_points(&self) -> Vec<Point2<f32>> {
+        let ship_transform =
+            nalgebra::Isometry2::new(Vector2::new(self.position.x, self.position.y), self.angle);
+        self.polygons
+            .polygons
+            .iter()
+            .flat_map(|poly| poly.iter())
+            .map(|&(x, y)| ship_transform * Point2::new(x, y))
+            .collect()
+    }
+
+    fn tick(&mut self, _ctx: &mut Context, environment: &Environment) -> GameResult {
         if !self.alive {
             return Ok(());
         }
         self.angle =
             (self.angle + self.angular_velocity * TICK_TIME) % (std::f32::consts::PI * 2.0);
 
-        self.velocity *= ENERGY_CONSERVATION.powf(TICK_TIME);
+        self.velocity *= environment.energy_conservation.powf(TICK_TIME)
+            * (1.0 - environment.drag).powf(TICK_TIME);
         let direction = Vector2::new(self.angle.cos(), self.angle.sin());
-        let mut acceleration = self.thrust * direction;
-        acceleration.y -= GRAVITY;
+        let acceleration = self.thrust * environment.thrust_multiplier * direction
+            + environment.gravity
+            + environment.wind;
         self.velocity += acceleration * TICK_TIME;
         self.position += self.velocity * TICK_TIME;
         Ok(())
@@ -133,47 +198,369 @@ impl Ship {
 
 struct LevelState {
     level_number: u32,
+    raw_meshes: RawMeshes,
+    extents: Extents,
     level_meshes: Vec<graphics::Mesh>,
-    collision_map: BitVec,
+    // Fill and stroke of the polygons of each group
+    group_meshes: Vec<Vec<graphics::Mesh>>,
+    // Whether each group's walls are there
+    group_enabled: Vec<bool>,
+    // Fill and stroke of each moving obstacle, relative to its pivot
+    obstacle_meshes: Vec<Vec<graphics::Mesh>>,
+    collision_map: CollisionMap,
+    // Images of collision map tiles for the debug overlay. Created when needed.
+    collision_images: HashMap<(u32, u32), graphics::Image>,
     triggers: HashMap<u32, Trigger>,
+    // Triggers that have fired
     shown_triggers: HashSet<u32>,
+    // Triggers that the ship was inside at the last tick
+    inside_triggers: HashSet<u32>,
+    // Triggers that will fire once their conditions hold: ones the ship is
+    // inside that haven't fired since it entered, and ones it just left that
+    // fire on exit
+    pending_triggers: HashSet<u32>,
+    // Level time when each trigger last fired, for cooldowns
+    trigger_times: HashMap<u32, f32>,
+    script: Script,
+    // In the "trigger" with ID 0
+    spawn_position: Point2<f32>,
+    physics: LevelPhysics,
+    // Seconds played on the level, for turbulence
+    time: f32,
+    wind_particles: WindParticles,
+}
+
+// The spawn position is a "trigger" with ID 0
+fn get_spawn_position(triggers: &[Trigger]) -> Option<Point2<f32>> {
+    let (x, y) = triggers.iter().find(|t| t.id == 0)?.get_inside_point();
+    Some(Point2::new(x, y))
 }
 
 impl LevelState {
-    fn get_spawn_position(&self) -> Point2<f32> {
-        // The spawn position is a "trigger" with ID 0
-        let spawn_trigger = self.triggers.get(&0u32).unwrap();
-        Point2::new(
-            (spawn_trigger.min_x + spawn_trigger.max_x) * 0.5,
-            (spawn_trigger.min_y + spawn_trigger.max_y) * 0.5,
-        )
+    // Get the physics at a position. The first zone that contains it applies,
+    // and the pull of all gravity wells and the wind is added.
+    fn get_environment(&self, position: Point2<f32>) -> Environment {
+        let mut environment = Environment {
+            gravity: Vector2::new(0.0, -self.physics.gravity),
+            wind: wind::get_wind(&self.raw_meshes.wind, position, self.time),
+            energy_conservation: self.physics.energy_conservation,
+            drag: 0.0,
+            thrust_multiplier: 1.0,
+        };
+        let zone = self
+            .raw_meshes
+            .zones
+            .iter()
+            .find(|zone| zone.contains(position.x, position.y));
+        if let Some(zone) = zone {
+            if let Some((x, y)) = zone.gravity {
+                environment.gravity = Vector2::new(x, y);
+            }
+            environment.drag = zone.drag;
+            environment.thrust_multiplier = zone.thrust_multiplier;
+        }
+        for well in self.raw_meshes.wells.iter() {
+            let (x, y) = well.get_acceleration(position.x, position.y);
+            environment.gravity += Vector2::new(x, y);
+        }
+        environment
+    }
+
+    // Create drawables and triggers from raw_meshes
+    fn update_meshes(&mut self, ctx: &mut Context) -> GameResult {
+        self.level_meshes = create_drawables(
+            ctx,
+            &self.raw_meshes.polygons,
+            Color::from_rgb_u32(FILL_COLOR),
+            Color::from_rgb_u32(WALL_COLOR),
+        )?;
+        self.triggers = self
+            .raw_meshes
+            .triggers
+            .iter()
+            .map(|t| (t.id, t.clone()))
+            .collect();
+        // The editor may have moved the spawn point, or removed it until
+        // it is put back
+        if let Some(position) = get_spawn_position(&self.raw_meshes.triggers) {
+            self.spawn_position = position;
+        }
+        // The editor may have removed or renumbered triggers
+        let triggers = &self.triggers;
+        self.inside_triggers.retain(|id| triggers.contains_key(id));
+        self.pending_triggers.retain(|id| triggers.contains_key(id));
+        self.group_meshes = self
+            .raw_meshes
+            .groups
+            .iter()
+            .map(|group| {
+                create_drawables(
+                    ctx,
+                    &group.polygons,
+                    Color::from_rgb_u32(FILL_COLOR),
+                    Color::from_rgb_u32(WALL_COLOR),
+                )
+            })
+            .collect::<GameResult<_>>()?;
+        self.obstacle_meshes = self
+            .raw_meshes
+            .obstacles
+            .iter()
+            .map(|obstacle| {
+                create_drawables(
+                    ctx,
+                    std::slice::from_ref(&obstacle.polygon),
+                    Color::from_rgb_u32(FILL_COLOR),
+                    Color::from_rgb_u32(WALL_COLOR),
+                )
+            })
+            .collect::<GameResult<_>>()?;
+        Ok(())
+    }
+
+    // Draw the static walls and the groups that are on
+    fn draw_walls(&self, ctx: &mut Context) -> GameResult {
+        let draw_param = graphics::DrawParam::default();
+        for (meshes, &enabled) in self.group_meshes.iter().zip(&self.group_enabled) {
+            if enabled {
+                for mesh in meshes {
+                    graphics::draw(ctx, mesh, draw_param)?;
+                }
+            }
+        }
+        for mesh in &self.level_meshes {
+            graphics::draw(ctx, mesh, draw_param)?;
+        }
+        Ok(())
+    }
+
+    // Draw the moving obstacles where they are at the level's current time
+    fn draw_obstacles(&self, ctx: &mut Context) -> GameResult {
+        for (obstacle, meshes) in self.raw_meshes.obstacles.iter().zip(&self.obstacle_meshes) {
+            let ((x, y), angle) = obstacle.get_transform(self.time);
+            let draw_param = graphics::DrawParam::default()
+                .dest(Point2::new(x, y))
+                .rotation(angle);
+            for mesh in meshes {
+                graphics::draw(ctx, mesh, draw_param)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Render collision map from level_meshes and the groups that are on
+    fn update_collision_map(&mut self, ctx: &mut Context) -> GameResult {
+        let extents = self.extents;
+        self.collision_map = CollisionMap::new(extents.collision_width, extents.collision_height);
+        self.render_collision_map(ctx, None)?;
+        println!(
+            "Collision map {}x{}: {} of {} tiles need a bitmap",
+            extents.collision_width,
+            extents.collision_height,
+            self.collision_map.get_mixed_tile_count(),
+            self.collision_map.tiles_x() * self.collision_map.tiles_y()
+        );
+        Ok(())
+    }
+
+    // Render the chunks of the collision map that overlap area, or only the
+    // ones with walls if there is no area, i.e. the map is empty.
+    fn render_collision_map(
+        &mut self,
+        ctx: &mut Context,
+        area: Option<graphics::Rect>,
+    ) -> GameResult {
+        let enabled_groups: Vec<usize> = (0..self.raw_meshes.groups.len())
+            .filter(|&i| self.group_enabled[i])
+            .collect();
+        let bounds: Vec<graphics::Rect> = self
+            .raw_meshes
+            .polygons
+            .iter()
+            .chain(
+                enabled_groups
+                    .iter()
+                    .flat_map(|&i| self.raw_meshes.groups[i].polygons.iter()),
+            )
+            .map(|polygon| get_bounds(polygon, STROKE_WIDTH))
+            .collect();
+        let level_meshes = &self.level_meshes;
+        let group_meshes = &self.group_meshes;
+        let collision_map = &mut self.collision_map;
+
+        render_in_chunks(
+            ctx,
+            &self.extents,
+            |rect| match area {
+                Some(area) => !area.overlaps(&rect),
+                None => !bounds.iter().any(|b| b.overlaps(&rect)),
+            },
+            |ctx| {
+                let draw_param = graphics::DrawParam::default();
+                for &i in enabled_groups.iter() {
+                    for mesh in &group_meshes[i] {
+                        graphics::draw(ctx, mesh, draw_param)?;
+                    }
+                }
+                for mesh in level_meshes {
+                    graphics::draw(ctx, mesh, draw_param)?;
+                }
+                Ok(())
+            },
+            |chunk_x, chunk_y, pixels| {
+                let tiles_per_chunk = CHUNK_SIZE / TILE_SIZE;
+                for ty in 0..tiles_per_chunk {
+                    for tx in 0..tiles_per_chunk {
+                        let tile_x = chunk_x / TILE_SIZE + tx;
+                        let tile_y = chunk_y / TILE_SIZE + ty;
+                        if tile_x >= collision_map.tiles_x() || tile_y >= collision_map.tiles_y() {
+                            continue;
+                        }
+                        collision_map.set_tile(tile_x, tile_y, |x, y| {
+                            let px = tx * TILE_SIZE + x;
+                            let py = ty * TILE_SIZE + y;
+                            let a = pixels[((py * CHUNK_SIZE + px) * 4 + 3) as usize];
+                            a >= 0x80
+                        });
+                    }
+                }
+                Ok(())
+            },
+        )?;
+        self.collision_images.clear();
+        Ok(())
+    }
+
+    // Turn a group of walls on or off. None toggles it.
+    fn set_group_enabled(
+        &mut self,
+        ctx: &mut Context,
+        name: &str,
+        enabled: Option<bool>,
+    ) -> GameResult {
+        let index = match self.raw_meshes.groups.iter().position(|g| g.name == name) {
+            Some(index) => index,
+            None => {
+                println!("No group {} on level {}", name, self.level_number);
+                return Ok(());
+            }
+        };
+        let enabled = enabled.unwrap_or(!self.group_enabled[index]);
+        if enabled == self.group_enabled[index] {
+            return Ok(());
+        }
+        self.group_enabled[index] = enabled;
+        let polygons = &self.raw_meshes.groups[index].polygons;
+        if let Some(first) = polygons.first() {
+            let area = polygons
+                .iter()
+                .map(|polygon| get_bounds(polygon, STROKE_WIDTH))
+                .fold(get_bounds(first, STROKE_WIDTH), |a, b| a.combine_with(b));
+            self.render_collision_map(ctx, Some(area))?;
+        }
+        Ok(())
     }
 
     fn get_collision(&self, position: Point2<f32>) -> bool {
-        if let Some(i) = LevelState::get_collider_map_index(position) {
-            return self.collision_map[i];
+        if self
+            .raw_meshes
+            .obstacles
+            .iter()
+            .any(|o| o.contains(self.time, position.x, position.y))
+        {
+            return true;
+        }
+        if let Some((x, y)) = self.get_collider_map_index(position) {
+            return self.collision_map.get(x, y);
         }
-        return false;
+        false
     }
 
-    fn get_collider_map_index(position: Point2<f32>) -> Option<usize> {
-        let r = ((position.y - LEVEL_EXTENTS.top()) * COLLISION_MAP_HEIGHT as f32 / LEVEL_EXTENTS.h)
+    // Get the pixel in the collision map for a position in the world
+    fn get_collider_map_index(&self, position: Point2<f32>) -> Option<(u32, u32)> {
+        let extents = &self.extents;
+        let width = extents.collision_width;
+        let height = extents.collision_height;
+
+        let r = ((position.y - extents.min_y) * height as f32 / (extents.max_y - extents.min_y))
             .round() as i32;
-        if r < 0 || r >= COLLISION_MAP_HEIGHT as i32 {
+        if r < 0 || r >= height as i32 {
             println!("Point is outside: {:?} collision row {}", position, r);
             return None;
         }
         let r = r as u32;
 
-        let c = ((position.x - LEVEL_EXTENTS.left()) * COLLISION_MAP_WIDTH as f32 / LEVEL_EXTENTS.w)
+        let c = ((position.x - extents.min_x) * width as f32 / (extents.max_x - extents.min_x))
             .round() as i32;
-        if c < 0 || c >= COLLISION_MAP_WIDTH as i32 {
+        if c < 0 || c >= width as i32 {
             println!("Point is outside: {:?} collision column {}", position, c);
             return None;
         }
         let c = c as u32;
 
-        return Some(((COLLISION_MAP_HEIGHT - 1 - r) * COLLISION_MAP_WIDTH + c) as usize);
+        Some((c, height - 1 - r))
+    }
+
+    // Distance from a position in the world to the closest wall pixel in the
+    // collision map or moving obstacle, if there is one within max_distance
+    fn get_wall_distance(&self, position: Point2<f32>, max_distance: f32) -> Option<f32> {
+        let extents = &self.extents;
+        let width = extents.collision_width as i64;
+        let height = extents.collision_height as i64;
+        let pixel_width = (extents.max_x - extents.min_x) / width as f32;
+        let pixel_height = (extents.max_y - extents.min_y) / height as f32;
+        // The position in pixels, with rows from the top like the collision map
+        let x = (position.x - extents.min_x) / pixel_width;
+        let y = (extents.max_y - position.y) / pixel_height;
+        let range = |center: f32, radius: f32, count: i64| {
+            let first = ((center - radius).floor() as i64).max(0);
+            let last = ((center + radius).ceil() as i64).min(count - 1);
+            first..=last
+        };
+        let columns = range(x, max_distance / pixel_width, width);
+        let rows = range(y, max_distance / pixel_height, height);
+        // Distance in the world from the position to a pixel, or to the
+        // closest point of a rectangle of pixels
+        let get_distance = |c0: i64, r0: i64, c1: i64, r1: i64| {
+            let dx = (x.clamp(c0 as f32, c1 as f32) - x) * pixel_width;
+            let dy = (y.clamp(r0 as f32, r1 as f32) - y) * pixel_height;
+            (dx * dx + dy * dy).sqrt()
+        };
+        let mut closest = self
+            .raw_meshes
+            .obstacles
+            .iter()
+            .map(|o| o.get_distance(self.time, position.x, position.y))
+            .fold(f32::INFINITY, f32::min);
+        if !columns.is_empty() && !rows.is_empty() {
+            let tile_size = TILE_SIZE as i64;
+            for tile_y in (*rows.start() / tile_size)..=(*rows.end() / tile_size) {
+                for tile_x in (*columns.start() / tile_size)..=(*columns.end() / tile_size) {
+                    // The pixels of the tile within range
+                    let c0 = (tile_x * tile_size).max(*columns.start());
+                    let c1 = (tile_x * tile_size + tile_size - 1).min(*columns.end());
+                    let r0 = (tile_y * tile_size).max(*rows.start());
+                    let r1 = (tile_y * tile_size + tile_size - 1).min(*rows.end());
+                    if get_distance(c0, r0, c1, r1) >= closest {
+                        continue;
+                    }
+                    match self.collision_map.get_tile(tile_x as u32, tile_y as u32) {
+                        Tile::Empty => {}
+                        Tile::Full => closest = get_distance(c0, r0, c1, r1),
+                        Tile::Mixed(_) => {
+                            for r in r0..=r1 {
+                                for c in c0..=c1 {
+                                    if self.collision_map.get(c as u32, r as u32) {
+                                        closest = closest.min(get_distance(c, r, c, r));
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Some(closest).filter(|&d| d <= max_distance)
     }
 }
 
@@ -181,105 +568,113 @@ fn load_level(ctx: &mut Context, level_number: u32) -> GameResult<LevelState> {
     // Level
 
     let f = ggez::filesystem::open(ctx, format!("/level{:02}.dat", level_number))?;
-    let raw_level_meshes = load_meshes(ctx, f)?;
-    let level_meshes = create_drawables(
-        ctx,
-        &raw_level_meshes,
-        Color::from_rgb_u32(FILL_COLOR),
-        Color::from_rgb_u32(WALL_COLOR),
-    )?;
+    let raw_meshes = load_meshes(ctx, f)?;
+    raw_meshes.validate()?;
 
-    let triggers: HashMap<u32, Trigger> = raw_level_meshes
-        .triggers
-        .iter()
-        .map(|t| (t.id, *t))
-        .collect();
+    // Script
 
-    // Render collision map
+    let mut f = ggez::filesystem::open(ctx, format!("/level{:02}.script", level_number))?;
+    let mut source = String::new();
+    f.read_to_string(&mut source)?;
+    let script = Script::parse(&source)?;
 
-    let canvas = graphics::Canvas::new(
+    create_level(
         ctx,
-        COLLISION_MAP_WIDTH as u16,
-        COLLISION_MAP_HEIGHT as u16,
-        conf::NumSamples::One,
-    )?;
-
-    graphics::set_canvas(ctx, Some(&canvas));
-    graphics::clear(ctx, [0.0, 0.0, 0.0, 0.0].into());
-    graphics::set_screen_coordinates(ctx, LEVEL_EXTENTS)?;
-
-    let draw_param = graphics::DrawParam::default();
-    for mesh in &level_meshes {
-        graphics::draw(ctx, mesh, draw_param)?;
-    }
-    graphics::present(ctx)?;
-
-    let image = canvas.into_inner();
-    let pixels = image.to_rgba8(ctx)?;
-    assert!(pixels.len() == (COLLISION_MAP_WIDTH * COLLISION_MAP_HEIGHT * 4) as usize);
-    let collision_map = BitVec::from_fn(
-        COLLISION_MAP_WIDTH as usize * COLLISION_MAP_HEIGHT as usize,
-        |i| {
-            let a = pixels[i * 4 + 3];
-            a >= 0x80
+        SectionData {
+            level_number,
+            raw_meshes,
+            script,
         },
-    );
-
-    // Print collision map
-    if false {
-        for y in 0..32 {
-            for x in 0..32 {
-                let bit = collision_map
-                    .get(
-                        ((y * COLLISION_MAP_HEIGHT / 32) * COLLISION_MAP_WIDTH
-                            + (x * COLLISION_MAP_WIDTH / 32)) as usize,
-                    )
-                    .unwrap();
-                print!("{}", if bit { 'X' } else { '.' });
-            }
-            println!();
-        }
-    }
-
-    graphics::set_canvas(ctx, None);
+    )
+}
 
-    Ok(LevelState {
-        level_number,
-        level_meshes,
-        collision_map,
-        triggers,
+// Create the drawables and collision map for a level that has been read
+fn create_level(ctx: &mut Context, data: SectionData) -> GameResult<LevelState> {
+    let spawn_position = get_spawn_position(&data.raw_meshes.triggers).ok_or_else(|| {
+        GameError::ResourceLoadError(format!(
+            "Level {} has no spawn point (trigger 0)",
+            data.level_number
+        ))
+    })?;
+    let mut level = LevelState {
+        level_number: data.level_number,
+        extents: data.raw_meshes.get_extents(),
+        physics: LevelPhysics::new(&data.raw_meshes.physics),
+        time: 0.0,
+        wind_particles: WindParticles::new(),
+        group_enabled: data.raw_meshes.groups.iter().map(|g| g.enabled).collect(),
+        raw_meshes: data.raw_meshes,
+        level_meshes: Vec::new(),
+        group_meshes: Vec::new(),
+        obstacle_meshes: Vec::new(),
+        collision_map: CollisionMap::new(0, 0),
+        collision_images: HashMap::new(),
+        triggers: HashMap::new(),
         shown_triggers: HashSet::new(),
-    })
+        inside_triggers: HashSet::new(),
+        pending_triggers: HashSet::new(),
+        trigger_times: HashMap::new(),
+        script: data.script,
+        spawn_position,
+    };
+    level.update_meshes(ctx)?;
+    level.update_collision_map(ctx)?;
+    Ok(level)
 }
 
 struct MainState {
     ship: Ship,
     font: graphics::Font,
-    ui_text: Option<graphics::Text>,
+    strings: Strings,
+    // Texts that pause the game
+    messages: Messages,
+    // Whether Return was down at the last update
+    return_pressed: bool,
+    subtitles: Subtitles,
     level: Option<LevelState>,
+    progress: Progress,
+    // Loaded sections of the same world as the current level
+    sections: Vec<LevelState>,
+    // Where the world was entered, for respawning without a checkpoint
+    spawn_position: Point2<f32>,
+    streamer: Streamer,
     wanted_level: u32,
-    _ambient: audio::Source,
+    // Whether the script ended the story, so the checkpoint is forgotten
+    // when the text is dismissed
+    story_over: bool,
+    level_watcher: Option<LevelWatcher>,
+    editor: Option<Editor>,
+    dev_mode: bool,
+    show_debug_overlay: bool,
+    settings: Settings,
+    // Open when the game is paused to change the settings
+    settings_menu: Option<SettingsMenu>,
+    music: Music,
     ping: audio::Source,
-    thrust_sound: audio::Source,
+    ship_audio: ShipAudio,
     explosion_sound: audio::Source,
 }
 
 impl MainState {
-    fn new(ctx: &mut Context, starting_level: u32) -> GameResult<MainState> {
+    fn new(
+        ctx: &mut Context,
+        starting_level: Option<u32>,
+        resource_dirs: Vec<path::PathBuf>,
+        dev_mode: bool,
+        language: &str,
+        new_game: bool,
+    ) -> GameResult<MainState> {
         // Audio
-        let mut ambient = audio::Source::new(ctx, "/music.ogg").unwrap();
-        let _ = ambient.play_detached();
-        ambient.set_repeat(true);
+        let settings = Settings::load(ctx);
 
         let ping = audio::Source::new(ctx, "/ping.ogg").unwrap();
-        let mut thrust_sound = audio::Source::new(ctx, "/thrust.wav").unwrap();
-        thrust_sound.set_volume(0.0);
-        thrust_sound.set_repeat(true);
+        let ship_audio = ShipAudio::new(ctx)?;
         let explosion_sound = audio::Source::new(ctx, "/explosion.ogg").unwrap();
 
         // Text
 
-        let font = graphics::Font::new(ctx, "/font/font.ttf")?;
+        let strings = Strings::load(ctx, language)?;
+        let font = graphics::Font::new(ctx, strings.get_font())?;
 
         // Ship
 
@@ -287,7 +682,7 @@ impl MainState {
         let ship_polygons = load_meshes(ctx, f)?;
         let ship_meshes = create_drawables(
             ctx,
-            &ship_polygons,
+            &ship_polygons.polygons,
             Color::from_rgb_u32(FILL_COLOR),
             Color::from_rgb_u32(SHIP_COLOR),
         )?;
@@ -295,9 +690,48 @@ impl MainState {
         let f = ggez::filesystem::open(ctx, "/ship-collider.dat")?;
         let collider_polygons = load_meshes(ctx, f)?;
 
+        // Worlds
+
+        let worlds = if ggez::filesystem::exists(ctx, "/worlds.txt") {
+            let mut f = ggez::filesystem::open(ctx, "/worlds.txt")?;
+            let mut source = String::new();
+            f.read_to_string(&mut source)?;
+            streaming::parse_worlds(&source)?
+        } else {
+            Vec::new()
+        };
+        let mut streamer = Streamer::new(resource_dirs.clone(), worlds);
+
+        // Continue from the saved checkpoint unless a level was given
+        let progress = if new_game {
+            // Forget the pickups and the checkpoint, also for the next time
+            let progress = Progress::default();
+            if let Err(e) = progress.save(ctx) {
+                println!("Failed to save progress: {}", e);
+            }
+            progress
+        } else {
+            Progress::load(ctx)
+        };
+        let checkpoint = match starting_level {
+            Some(_) => None,
+            None => progress.checkpoint,
+        };
+        let starting_level = match checkpoint {
+            Some(c) => c.level_number,
+            None => starting_level.unwrap_or(1),
+        };
+
         let level = load_level(ctx, starting_level)?;
-        let ship = Ship {
-            position: level.get_spawn_position(),
+        streamer.enter(starting_level, level.extents);
+        let mut music = Music::new();
+        let (track, _) = level
+            .script
+            .get_level_music()
+            .unwrap_or((DEFAULT_TRACK, None));
+        music.play(ctx, track, 0.0);
+        let mut ship = Ship {
+            position: level.spawn_position,
             velocity: Vector2::new(0.0, 0.0),
             angle: std::f32::consts::FRAC_PI_2,
             angular_velocity: 0.0,
@@ -309,212 +743,621 @@ impl MainState {
             thrust_enabled: starting_level != 1,
             turning_enabled: starting_level != 1,
         };
+        if let Some(c) = checkpoint {
+            println!("Continuing from checkpoint on level {}", c.level_number);
+            ship.reset(Point2::new(c.x, c.y), c.angle);
+            ship.thrust_enabled = c.thrust_enabled;
+            ship.turning_enabled = c.turning_enabled;
+        }
 
         Ok(MainState {
+            spawn_position: level.spawn_position,
             ship,
             font,
-            ui_text: None,
+            strings,
+            messages: Messages::new(font),
+            return_pressed: false,
+            subtitles: Subtitles::new(),
             level: Some(level),
+            progress,
+            sections: Vec::new(),
+            streamer,
             wanted_level: starting_level,
-            _ambient: ambient,
+            story_over: false,
+            level_watcher: if dev_mode {
+                Some(LevelWatcher::new(resource_dirs.clone(), starting_level))
+            } else {
+                None
+            },
+            dev_mode,
+            show_debug_overlay: false,
+            editor: if dev_mode {
+                Some(Editor::new(resource_dirs))
+            } else {
+                None
+            },
+            settings,
+            settings_menu: None,
+            music,
             ping,
-            thrust_sound,
+            ship_audio,
             explosion_sound,
         })
     }
 
     fn update_during_play(&mut self, ctx: &mut Context) -> GameResult<Option<u32>> {
-        let level = self.level.as_ref().unwrap();
-
         let mut hit_trigger = None;
 
-        self.ship.update(ctx)?;
+        self.ship
+            .update(ctx, &self.level.as_ref().unwrap().physics)?;
         while timer::check_update_time(ctx, TICKS_PER_SECOND) && hit_trigger.is_none() {
-            self.ship.tick(ctx)?;
+            // All loaded sections, so their obstacles don't jump when entered
+            for level in self.level.iter_mut().chain(self.sections.iter_mut()) {
+                level.time += TICK_TIME;
+            }
+            let level = self.level.as_ref().unwrap();
+            let environment = level.get_environment(self.ship.position);
+            self.ship.tick(ctx, &environment)?;
 
             if self.ship.alive {
-                let ship_transform = nalgebra::Isometry2::new(
-                    Vector2::new(self.ship.position.x, self.ship.position.y),
-                    self.ship.angle,
-                );
-                //println!("Ship transform: {}", ship_transform);
-
-                let mut collided = false;
-                for poly in self.ship.polygons.polygons.iter() {
-                    for &(x, y) in poly.iter() {
-                        let point = ship_transform * Point2::new(x, y);
-                        let hit = level.get_collision(point.into());
-                        if hit {
-                            //println!("Collided at {}", point);
-                            collided = true;
-                        }
-                    }
-                }
+                let collided = self
+                    .ship
+                    .get_collider_points()
+                    .into_iter()
+                    .any(|point| self.get_collision(point));
 
                 if collided {
                     self.ship.alive = false;
+                    self.explosion_sound
+                        .set_volume(self.settings.get_volume(Channel::Effects));
                     let _ = self.explosion_sound.play();
                 } else {
-                    for (&trigger_id, trigger) in level.triggers.iter() {
-                        if trigger.min_x <= self.ship.position.x
-                            && self.ship.position.x < trigger.max_x
-                            && trigger.min_y <= self.ship.position.y
-                            && self.ship.position.y < trigger.max_y
-                            && !level.shown_triggers.contains(&trigger_id)
-                        {
-                            println!("In trigger {}: {}", trigger_id, self.ship.position);
-                            hit_trigger = Some(trigger_id);
-                            break;
-                        }
-                    }
+                    self.collect_pickups(ctx)?;
+                    hit_trigger = self.update_triggers();
                 }
             } else {
                 self.ship.dead_time += TICK_TIME;
             }
         }
         if !self.ship.alive && self.ship.dead_time >= DEAD_TIMEOUT {
-            self.show_text(ctx, "Ouch! ... I wonder why that felt familiar.");
+            let text = self
+                .strings
+                .get("ouch", "Ouch! ... I wonder why that felt familiar.")
+                .to_string();
+            self.show_text(ctx, &[&text]);
         }
         Ok(hit_trigger)
     }
 
+    // Pick up anything the ship's collider touches
+    fn collect_pickups(&mut self, ctx: &mut Context) -> GameResult {
+        let level = self.level.as_ref().unwrap();
+        let points = self.ship.get_collider_points();
+        let touched: Vec<u32> = level
+            .raw_meshes
+            .pickups
+            .iter()
+            .filter(|p| !self.progress.is_collected(level.level_number, p.id))
+            .filter(|p| {
+                points.iter().any(|point| {
+                    (point.x - p.x).powi(2) + (point.y - p.y).powi(2) <= p.radius * p.radius
+                })
+            })
+            .map(|p| p.id)
+            .collect();
+        if touched.is_empty() {
+            return Ok(());
+        }
+        let level_number = level.level_number;
+        for id in touched {
+            println!("Collected pickup {}", id);
+            self.progress.collect(level_number, id);
+        }
+        self.ping
+            .set_volume(self.settings.get_volume(Channel::Effects));
+        let _ = self.ping.play();
+        if let Err(e) = self.progress.save(ctx) {
+            println!("Failed to save progress: {}", e);
+        }
+        Ok(())
+    }
+
+    // Draw the pickups that haven't been collected, on all loaded levels
+    fn draw_pickups(&self, ctx: &mut Context) -> GameResult {
+        let mut builder = graphics::MeshBuilder::new();
+        let mut count = 0;
+        for level in self.level.iter().chain(self.sections.iter()) {
+            for pickup in level.raw_meshes.pickups.iter() {
+                if self.progress.is_collected(level.level_number, pickup.id) {
+                    continue;
+                }
+                builder.circle(
+                    graphics::DrawMode::Stroke(
+                        graphics::StrokeOptions::default().with_line_width(STROKE_WIDTH),
+                    ),
+                    Point2::new(pickup.x, pickup.y),
+                    pickup.radius,
+                    0.1,
+                    Color::from_rgb_u32(PICKUP_COLOR),
+                );
+                builder.circle(
+                    graphics::DrawMode::Fill(graphics::FillOptions::default()),
+                    Point2::new(pickup.x, pickup.y),
+                    pickup.radius * 0.4,
+                    0.1,
+                    Color::from_rgb_u32(PICKUP_COLOR),
+                );
+                count += 1;
+            }
+        }
+        if count != 0 {
+            let mesh = builder.build(ctx)?;
+            graphics::draw(ctx, &mesh, graphics::DrawParam::default())?;
+        }
+        Ok(())
+    }
+
+    // Number of pickups collected on the current level, and the total
+    fn get_pickup_count(&self) -> (usize, usize) {
+        let level = self.level.as_ref().unwrap();
+        let pickups = &level.raw_meshes.pickups;
+        let collected = pickups
+            .iter()
+            .filter(|p| self.progress.is_collected(level.level_number, p.id))
+            .count();
+        (collected, pickups.len())
+    }
+
+    // Find the trigger that fires, if any
+    fn update_triggers(&mut self) -> Option<u32> {
+        let position = self.ship.position;
+        let collider_points = self.ship.get_collider_points();
+        let thrusting = self.ship.thrust > 0.0;
+        let speed = self.ship.velocity.norm();
+        let level = self.level.as_mut().unwrap();
+        let inside: HashSet<u32> = level
+            .triggers
+            .iter()
+            .filter(|(_, trigger)| {
+                trigger.contains(position.x, position.y)
+                    || (trigger.whole_ship
+                        && collider_points.iter().any(|p| trigger.contains(p.x, p.y)))
+            })
+            .map(|(&id, _)| id)
+            .collect();
+
+        let script = &level.script;
+        let on_exit = |id: &u32| script.get_options(*id).on_exit;
+        let entered: Vec<u32> = inside
+            .difference(&level.inside_triggers)
+            .copied()
+            .filter(|id| !on_exit(id))
+            .collect();
+        let exited: Vec<u32> = level
+            .inside_triggers
+            .difference(&inside)
+            .copied()
+            .filter(on_exit)
+            .collect();
+        level
+            .pending_triggers
+            .retain(|id| inside.contains(id) || on_exit(id));
+        level.pending_triggers.extend(entered);
+        level.pending_triggers.extend(exited);
+        level.inside_triggers = inside;
+
+        let mut candidates: Vec<u32> = level.pending_triggers.iter().copied().collect();
+        candidates.sort_unstable_by_key(|id| {
+            let priority = level.triggers.get(id).map_or(0, |t| t.priority);
+            (std::cmp::Reverse(priority), *id)
+        });
+        let fired = candidates.iter().position(|&id| {
+            let options = script.get_options(id);
+            let ready = !level.shown_triggers.contains(&id)
+                || options.repeat.is_some_and(|cooldown| {
+                    level
+                        .trigger_times
+                        .get(&id)
+                        .is_none_or(|&t| level.time - t >= cooldown)
+                });
+            ready
+                && options.thrusting.is_none_or(|t| t == thrusting)
+                && options
+                    .visited
+                    .iter()
+                    .all(|v| level.shown_triggers.contains(v))
+                && options.max_speed.is_none_or(|max| speed < max)
+        });
+        // Exit events only get one chance. The ones after the trigger that
+        // fires get theirs next tick.
+        let checked = fired.map_or(candidates.len(), |i| i + 1);
+        for id in candidates[..checked].iter() {
+            if on_exit(id) {
+                level.pending_triggers.remove(id);
+            }
+        }
+        let fired = fired.map(|i| candidates[i]);
+        if let Some(trigger_id) = fired {
+            println!("Trigger {} fired: {}", trigger_id, position);
+            level.pending_triggers.remove(&trigger_id);
+            level.trigger_times.insert(trigger_id, level.time);
+        }
+        fired
+    }
+
     fn execute_trigger(&mut self, ctx: &mut Context, trigger_id: u32) -> GameResult {
+        let (collected, total) = self.get_pickup_count();
         let level = self.level.as_mut().unwrap();
         level.shown_triggers.insert(trigger_id);
 
-        let text: Option<String> = match (level.level_number, trigger_id) {
-            (_, 0) => {
-                // ignore hitting the spawn point
-                None
-            }
-            (1, 10) => {
-                Some("What's this? What happened? Am I falling?".to_string())
-            }
-            (1, 11) => {
-                Some("I'm in some kind of aircraft. Can I control it?".to_string())
-            }
-            (1, 12) => {
-                Some("Nothing. I'm going to crash!".to_string())
-            }
-            (1, 13) => {
-                self.ship.thrust_enabled = true;
-                Some("Wait! I feel it... Go up!".to_string())
-            }
-            (1, 14) => {
-                Some("Up! Up! Up!".to_string())
-            }
-            (1, 20) => {
-                self.ship.turning_enabled = true;
-                Some("I think I know how to turn left and right...".to_string())
-            }
-            (1, 21) => {
-                Some("This feels stangely natural. I should be a pilot!".to_string())
+        let actions = level.script.get_actions(trigger_id).map(|a| a.to_vec());
+        let strings = &self.strings;
+        let translate = |english: &str| strings.get(english, english).to_string();
+        let mut checkpoint_angle = None;
+        let mut music = None;
+        let mut subtitles = Vec::new();
+        let texts: Vec<String> = match actions {
+            Some(actions) => {
+                let mut texts = Vec::new();
+                for action in actions.iter() {
+                    match action {
+                        Action::Say(t) => texts.push(translate(t)),
+                        Action::Subtitle(t) => subtitles.push(translate(t)),
+                        Action::Enable(Ability::Thrust) => self.ship.thrust_enabled = true,
+                        Action::Enable(Ability::Turning) => self.ship.turning_enabled = true,
+                        Action::GotoLevel(n) => self.wanted_level = *n,
+                        Action::End => self.story_over = true,
+                        Action::ShowGroup(name) => {
+                            level.set_group_enabled(ctx, name, Some(true))?
+                        }
+                        Action::HideGroup(name) => {
+                            level.set_group_enabled(ctx, name, Some(false))?
+                        }
+                        Action::ToggleGroup(name) => level.set_group_enabled(ctx, name, None)?,
+                        Action::Checkpoint(angle) => checkpoint_angle = Some(*angle),
+                        Action::Music(track, duration) => {
+                            music = Some((track.clone(), duration.unwrap_or(DEFAULT_CROSSFADE)))
+                        }
+                        Action::Require(count, t) => {
+                            let needed = count.map_or(total, |c| c as usize);
+                            let text = translate(t);
+                            if collected < needed {
+                                // Not used up, so it works next time
+                                level.shown_triggers.remove(&trigger_id);
+                                texts = vec![text];
+                                break;
+                            }
+                        }
+                    }
+                }
+                texts
             }
-            (1, 22) => {
-                self.wanted_level = 2;
-                Some("Maybe I am a pilot? I don't remember anything.".to_string())
+            None if trigger_id == 0 => {
+                // ignore hitting the spawn point
+                Vec::new()
             }
+            None => vec![format!(
+                "Hit unknown trigger {} on level {:?}. This is a bug.",
+                trigger_id, level.level_number
+            )],
+        };
+        // After the other actions, so abilities enabled by this trigger are kept
+        if let Some(angle) = checkpoint_angle {
+            let trigger = &self.level.as_ref().unwrap().triggers[&trigger_id];
+            let (x, y) = trigger.get_inside_point();
+            let position = Point2::new(x, y);
+            println!("Checkpoint at trigger {}", trigger_id);
+            self.set_checkpoint(ctx, position, angle);
+        }
 
-            // Level 2
-            (2, 9) => {
-                    Some("What am I doing here? I feel strange. Where are my arms?".to_string())
-            }
-            (2, 10) => {
-                Some("I remember something. A woman. That's all.".to_string())
-            }
-            (2, 11) => {
-                Some("Pilot training! I did pilot training!\nThousands of training missions. Millions even.".to_string())
-            }
-            (2, 12) => {
-                Some("Dogfights. Low altitude precision flight.\nHigh speed pursuits.\nBut I don't remember any people.".to_string())
+        if let Some((track, duration)) = music {
+            self.music.play(ctx, &track, duration);
+        }
+        for subtitle in subtitles.iter() {
+            self.subtitles.add(ctx, self.font, subtitle);
+        }
+        if !texts.is_empty() {
+            let texts: Vec<&str> = texts.iter().map(|t| t.as_str()).collect();
+            self.show_text(ctx, &texts);
+        }
+
+        Ok(())
+    }
+
+    fn show_text(&mut self, ctx: &mut Context, texts: &[&str]) {
+        self.ping
+            .set_volume(self.settings.get_volume(Channel::Interface));
+        let _ = self.ping.play();
+        self.messages.push(ctx, texts);
+    }
+
+    // Fade to the current level's music, if it has any
+    fn start_level_music(&mut self, ctx: &mut Context) {
+        let level = self.level.as_ref().unwrap();
+        if let Some((track, duration)) = level.script.get_level_music() {
+            self.music
+                .play(ctx, track, duration.unwrap_or(DEFAULT_CROSSFADE));
+        }
+    }
+
+    // Use changed settings and save them
+    fn apply_settings(&mut self, ctx: &mut Context) {
+        if let Err(e) = self.settings.save(ctx) {
+            println!("Failed to save settings: {}", e);
+        }
+    }
+
+    // Load the current level again, keeping the ship where it is.
+    fn reload_level(&mut self, ctx: &mut Context) {
+        let level_number = self.level.as_ref().unwrap().level_number;
+        match load_level(ctx, level_number) {
+            Ok(mut level) => {
+                println!("Reloaded level {}", level_number);
+                let previous = self.level.take().unwrap();
+                level.shown_triggers = previous.shown_triggers;
+                // So triggers the ship is in don't fire again and obstacles
+                // don't jump back
+                level.inside_triggers = previous
+                    .inside_triggers
+                    .into_iter()
+                    .filter(|id| level.triggers.contains_key(id))
+                    .collect();
+                level.trigger_times = previous.trigger_times;
+                level.time = previous.time;
+                if previous.group_enabled.len() == level.group_enabled.len()
+                    && previous.group_enabled != level.group_enabled
+                {
+                    level.group_enabled = previous.group_enabled;
+                    if let Err(e) = level.update_collision_map(ctx) {
+                        println!("Failed to update collision map: {}", e);
+                    }
+                }
+                self.level = Some(level);
             }
-            (2, 13) => {
-                Some("That woman again! Is that a memory, a real memory?".to_string())
+            Err(e) => println!("Failed to reload level {}: {}", level_number, e),
+        }
+    }
+
+    // Distance to the closest wall in the level or the loaded sections next
+    // to it, so walls across a section border are warned about too
+    fn get_wall_distance(&self, position: Point2<f32>, max_distance: f32) -> Option<f32> {
+        self.level
+            .iter()
+            .chain(self.sections.iter())
+            .filter_map(|level| level.get_wall_distance(position, max_distance))
+            .reduce(f32::min)
+    }
+
+    // Load and unload sections around the ship, and switch to the section it is in
+    fn update_sections(&mut self, ctx: &mut Context) -> GameResult {
+        let position = self.ship.position;
+        let mut loaded: HashSet<u32> = self.sections.iter().map(|s| s.level_number).collect();
+        loaded.insert(self.level.as_ref().unwrap().level_number);
+
+        for data in self.streamer.receive() {
+            let level_number = data.level_number;
+            if loaded.contains(&level_number)
+                || !self
+                    .streamer
+                    .is_near(level_number, position, UNLOAD_DISTANCE)
+            {
+                // Only read to find its extents, or no longer needed
+                continue;
             }
-            (2, 14) => {
-                self.wanted_level = 3;
-                Some("Mom?".to_string())
+            let mut section = create_level(ctx, data)?;
+            if let Some(state) = self.streamer.take_state(level_number) {
+                section.shown_triggers = state.shown_triggers;
+                if state.group_enabled != section.group_enabled
+                    && state.group_enabled.len() == section.group_enabled.len()
+                {
+                    section.group_enabled = state.group_enabled;
+                    section.update_collision_map(ctx)?;
+                }
             }
+            println!("Loaded section {}", level_number);
+            self.sections.push(section);
+            loaded.insert(level_number);
+        }
 
-            // Level 3
-            (3, 10) => { Some("Am I dreaming? I don't exist.\nOnly this ship is real.".to_string())}
-            (3, 11) => { Some("I am this ship.".to_string())}
-            (3, 12) => { Some("I have memories of something else.\nMom, why did you leave me?".to_string())}
-            (3, 13) => { Some("It was Christmas. After my birthday.\nWe were going to New York.".to_string())}
-            (3, 14) => { Some("The plane crash! Then darkness. Hearing nothing, feeling nothing.".to_string())}
-            (3, 15) => {
-                Some("Mom next to the bed.\n... signed me away.".to_string())
-            }
-            (3, 16) => {
-                self.wanted_level = 1;
-                Some("Out of content. Thanks for playing!".to_string())
+        let streamer = &mut self.streamer;
+        let checkpoint_level = self.progress.checkpoint.map(|c| c.level_number);
+        self.sections.retain(|section| {
+            // Keep the checkpoint's section so the ship can respawn there
+            let keep = streamer.is_near(section.level_number, position, UNLOAD_DISTANCE)
+                || checkpoint_level == Some(section.level_number);
+            if !keep {
+                println!("Unloaded section {}", section.level_number);
+                loaded.remove(&section.level_number);
+                streamer.store_state(
+                    section.level_number,
+                    SectionState {
+                        shown_triggers: section.shown_triggers.clone(),
+                        group_enabled: section.group_enabled.clone(),
+                    },
+                );
             }
+            keep
+        });
+        self.streamer.request_near(position, &loaded);
 
-            _ => {
-                Some(
-                    format!(
-                        "Hit unknown trigger {} on level {:?}. This is a bug.",
-                        trigger_id, level.level_number
-                    ),
-                )
+        let level = self.level.as_ref().unwrap();
+        if !level.extents.rect().contains(position) {
+            if let Some(i) = self
+                .sections
+                .iter()
+                .position(|s| s.extents.rect().contains(position))
+            {
+                let section = self.sections.swap_remove(i);
+                println!("Entered section {}", section.level_number);
+                self.wanted_level = section.level_number;
+                let previous = self.level.replace(section).unwrap();
+                self.sections.push(previous);
+                self.start_level_music(ctx);
             }
-        };
-        if let Some(text) = text {
-            self.show_text(ctx, &text);
         }
-
         Ok(())
     }
 
-    fn show_text(&mut self, _ctx: &mut Context, t: &str) {
-        let _ = self.ping.play();
-        let mut text = graphics::Text::new(t);
-        text.set_font(self.font, graphics::Scale::uniform(FONT_SIZE));
-        self.ui_text = Some(text);
+    // Check for collision in whichever loaded section the point is in
+    fn get_collision(&self, point: Point2<f32>) -> bool {
+        let level = self.level.as_ref().unwrap();
+        if !level.extents.rect().contains(point) {
+            if let Some(section) = self
+                .sections
+                .iter()
+                .find(|s| s.extents.rect().contains(point))
+            {
+                return section.get_collision(point);
+            }
+        }
+        level.get_collision(point)
     }
 
+    // Respawn at the last checkpoint, or at the spawn point of the world if
+    // the checkpoint isn't on a loaded level, e.g. if there is none
     fn restart_level(&mut self) {
-        self.ship
-            .reset(self.level.as_ref().unwrap().get_spawn_position());
+        let checkpoint = self.progress.checkpoint.filter(|c| {
+            self.level
+                .iter()
+                .chain(self.sections.iter())
+                .any(|l| l.level_number == c.level_number)
+        });
+        match checkpoint {
+            Some(c) => {
+                self.ship.reset(Point2::new(c.x, c.y), c.angle);
+                self.ship.thrust_enabled = c.thrust_enabled;
+                self.ship.turning_enabled = c.turning_enabled;
+            }
+            None => self
+                .ship
+                .reset(self.spawn_position, std::f32::consts::FRAC_PI_2),
+        }
+    }
+
+    // Respawn here, with the ship's current abilities, and save it
+    fn set_checkpoint(&mut self, ctx: &mut Context, position: Point2<f32>, angle: f32) {
+        self.progress.checkpoint = Some(Checkpoint {
+            level_number: self.level.as_ref().unwrap().level_number,
+            x: position.x,
+            y: position.y,
+            angle,
+            thrust_enabled: self.ship.thrust_enabled,
+            turning_enabled: self.ship.turning_enabled,
+        });
+        if let Err(e) = self.progress.save(ctx) {
+            println!("Failed to save progress: {}", e);
+        }
+    }
+}
+
+impl MainState {
+    fn is_editing(&self) -> bool {
+        self.editor.as_ref().is_some_and(|e| e.active)
     }
 }
 
 impl event::EventHandler for MainState {
     fn update(&mut self, ctx: &mut Context) -> GameResult {
-        let ui_displayed = self.ui_text.is_some();
-        if ui_displayed {
-            if input::keyboard::is_key_pressed(ctx, KeyCode::Return) {
-                self.ui_text = None;
+        let editing = self.is_editing();
+        if editing {
+            self.editor.as_mut().unwrap().update(ctx);
+        }
+
+        // The settings menu pauses everything
+        let ui_displayed = !self.messages.is_empty() || self.settings_menu.is_some();
+        // Only when it goes down, so holding it doesn't skip pages
+        let return_pressed = input::keyboard::is_key_pressed(ctx, KeyCode::Return);
+        let return_went_down = return_pressed && !self.return_pressed;
+        self.return_pressed = return_pressed;
+        if ui_displayed && !editing {
+            if return_went_down && self.settings_menu.is_none() && self.messages.advance() {
                 if !self.ship.alive {
                     // It's the game over text
                     self.restart_level();
-                } else if self.wanted_level != self.level.as_ref().unwrap().level_number {
-                    self.level = Some(load_level(ctx, self.wanted_level)?);
-                    self.restart_level();
+                } else {
+                    if self.wanted_level != self.level.as_ref().unwrap().level_number {
+                        let level = load_level(ctx, self.wanted_level)?;
+                        self.streamer.enter(level.level_number, level.extents);
+                        let spawn_position = level.spawn_position;
+                        self.spawn_position = spawn_position;
+                        self.level = Some(level);
+                        self.sections.clear();
+                        self.start_level_music(ctx);
+                        self.ship.reset(spawn_position, std::f32::consts::FRAC_PI_2);
+                        if !self.story_over {
+                            // Continue from the start of this level next time
+                            let angle = std::f32::consts::FRAC_PI_2;
+                            self.set_checkpoint(ctx, spawn_position, angle);
+                        }
+                    }
+                    if self.story_over {
+                        // Start a new game next time
+                        self.story_over = false;
+                        self.progress.checkpoint = None;
+                        if let Err(e) = self.progress.save(ctx) {
+                            println!("Failed to save progress: {}", e);
+                        }
+                    }
                 }
             } else {
                 timer::sleep(timer::f64_to_duration(0.01));
             }
         }
 
-        if !ui_displayed && self.level.is_some() {
+        if let (Some(watcher), Some(level)) = (self.level_watcher.as_mut(), self.level.as_ref()) {
+            // Don't throw away changes made in the editor
+            if !editing && watcher.poll(level.level_number) {
+                self.reload_level(ctx);
+            }
+        }
+
+        if !ui_displayed && !editing && self.level.is_some() {
+            self.update_sections(ctx)?;
+            let delta_time = timer::duration_to_f64(timer::delta(ctx)) as f32;
+            self.subtitles.update(delta_time);
+            for level in self.level.iter_mut().chain(self.sections.iter_mut()) {
+                level
+                    .wind_particles
+                    .update(&level.raw_meshes.wind, level.time, delta_time);
+            }
             let r = self.update_during_play(ctx)?;
             if let Some(trigger_id) = r {
                 self.execute_trigger(ctx, trigger_id)?;
             }
         }
 
-        let thrust_volume = if self.ship.alive {
-            if self.ui_text.is_none() {
-                self.ship.thrust * 0.30 / THRUST
-            } else {
-                self.ship.thrust * 0.15 / THRUST
+        let frame_time = timer::duration_to_f64(timer::delta(ctx)) as f32;
+        self.music
+            .update(frame_time, self.settings.get_volume(Channel::Music));
+
+        let max_thrust = self.level.as_ref().map_or(THRUST, |l| l.physics.thrust);
+        let effects_volume = self.settings.get_volume(Channel::Effects);
+        let ship_volume = if editing || self.settings_menu.is_some() || !self.ship.alive {
+            0.0
+        } else if self.messages.is_empty() {
+            effects_volume
+        } else {
+            effects_volume * 0.5
+        };
+        // Only warn while flying
+        let wall_distance = match self.level.as_ref() {
+            Some(_) if !ui_displayed && !editing && self.ship.alive => {
+                self.get_wall_distance(self.ship.position, WARNING_DISTANCE)
             }
+            _ => None,
+        };
+        let thrust = if max_thrust > 0.0 {
+            self.ship.thrust / max_thrust
         } else {
             0.0
         };
-        self.thrust_sound.set_volume(thrust_volume);
-        self.thrust_sound.play_later()?;
+        self.ship_audio.update(
+            frame_time,
+            thrust,
+            self.ship.velocity.norm(),
+            wall_distance,
+            ship_volume,
+        );
 
         // There must be a better way to make sure we waste the time?
         while timer::check_update_time(ctx, TICKS_PER_SECOND) {}
@@ -524,34 +1367,24 @@ impl event::EventHandler for MainState {
     fn draw(&mut self, ctx: &mut Context) -> GameResult {
         graphics::clear(ctx, graphics::Color::from_rgb_u32(BACKGROUND_COLOR));
 
-        let camera_position = self.ship.position;
-
-        let (world_draw_rect, ui_draw_rect) = {
-            let (win_width, win_height) = graphics::drawable_size(ctx);
-            let aspect = if win_height != 0.0 {
-                win_width / win_height
-            } else {
-                1.0
-            };
-            let height = VISIBLE_HEIGHT;
-            let width = height * aspect;
-            let mut world_rect = graphics::Rect::new(-width * 0.5, height * 0.5, width, -height);
-            world_rect.translate(Vector2::new(camera_position.x, camera_position.y));
-
-            let ui_rect = graphics::Rect::new(0.0, 0.0, 800.0, 800.0 * win_height / win_width);
-            (world_rect, ui_rect)
+        let (camera_position, visible_height) = match self.editor.as_ref().filter(|e| e.active) {
+            Some(editor) => (editor.camera, editor.visible_height),
+            None => (self.ship.position, VISIBLE_HEIGHT),
         };
 
+        let (world_draw_rect, ui_draw_rect) = get_draw_rects(ctx, camera_position, visible_height);
+
         let draw_param = graphics::DrawParam::default();
 
         graphics::set_screen_coordinates(ctx, world_draw_rect)?;
 
         // Draw level
-        if let Some(level) = &self.level {
-            for mesh in &level.level_meshes {
-                graphics::draw(ctx, mesh, draw_param)?;
-            }
+        for level in self.level.iter().chain(self.sections.iter()) {
+            level.draw_walls(ctx)?;
+            level.draw_obstacles(ctx)?;
+            level.wind_particles.draw(ctx)?;
         }
+        self.draw_pickups(ctx)?;
 
         // Draw ship
         if self.ship.alive {
@@ -563,53 +1396,226 @@ impl event::EventHandler for MainState {
             }
         }
 
+        if self.show_debug_overlay {
+            let pixel_size = visible_height / graphics::drawable_size(ctx).1.max(1.0);
+            for section in self.sections.iter_mut() {
+                debug_overlay::draw_collision_map(ctx, section, world_draw_rect)?;
+            }
+            if let Some(level) = self.level.as_mut() {
+                debug_overlay::draw_world(ctx, level, &self.ship, world_draw_rect, pixel_size)?;
+            }
+        }
+
+        let editor = self.editor.as_ref().filter(|e| e.active);
+        if let (Some(editor), Some(level)) = (editor, &self.level) {
+            editor.draw_world(ctx, level)?;
+        }
+
         // Draw UI
 
         graphics::set_screen_coordinates(ctx, ui_draw_rect)?;
 
-        if let Some(text) = self.ui_text.as_ref() {
+        if let (Some(editor), Some(level)) = (editor, &self.level) {
+            editor.draw_ui(ctx, level, self.font, ui_draw_rect)?;
+        } else if self.show_debug_overlay {
+            // The editor shows trigger IDs too, so only do this when not editing
+            if let Some(level) = &self.level {
+                debug_overlay::draw_ui(ctx, level, self.font, world_draw_rect, ui_draw_rect)?;
+            }
+        }
+
+        let (collected, total) = self.get_pickup_count();
+        if total != 0 && editor.is_none() {
+            let mut text = graphics::Text::new(format!("{}/{}", collected, total));
+            text.set_font(self.font, graphics::Scale::uniform(HUD_FONT_SIZE));
+            let width = text.width(ctx) as f32;
             graphics::draw(
                 ctx,
-                text,
-                draw_param.color(graphics::Color::from_rgb_u32(0x00ff00)),
+                &text,
+                draw_param
+                    .dest(Point2::new(ui_draw_rect.w - width - 10.0, 10.0))
+                    .color(Color::from_rgb_u32(PICKUP_COLOR)),
             )?;
         }
 
+        if editor.is_none() {
+            self.subtitles.draw(ctx, self.font, ui_draw_rect)?;
+        }
+
+        let prompt = self.strings.get("press_enter", "Press Enter").to_string();
+        self.messages.draw(ctx, ui_draw_rect, &prompt)?;
+
+        if let Some(menu) = self.settings_menu.as_ref() {
+            menu.draw(ctx, self.font, ui_draw_rect, &self.settings, &self.strings)?;
+        }
+
         graphics::present(ctx)?;
         Ok(())
     }
+
+    fn key_down_event(
+        &mut self,
+        ctx: &mut Context,
+        keycode: KeyCode,
+        keymods: KeyMods,
+        repeat: bool,
+    ) {
+        if repeat && (keycode == KeyCode::M || keycode == KeyCode::F1) {
+            // Holding them would toggle over and over
+            return;
+        }
+        if keycode == KeyCode::Escape {
+            if self.settings_menu.is_some() {
+                self.settings_menu = None;
+            } else {
+                event::quit(ctx);
+            }
+            return;
+        }
+        if keycode == KeyCode::M {
+            self.settings.muted = !self.settings.muted;
+            self.apply_settings(ctx);
+        }
+        if keycode == KeyCode::F1 && !self.is_editing() {
+            self.settings_menu = match self.settings_menu {
+                Some(_) => None,
+                None => Some(SettingsMenu::new()),
+            };
+            return;
+        }
+        if let Some(menu) = self.settings_menu.as_mut() {
+            if menu.key_down(&mut self.settings, keycode) {
+                self.apply_settings(ctx);
+            }
+            return;
+        }
+        if keycode == KeyCode::F3 && self.dev_mode {
+            self.show_debug_overlay = !self.show_debug_overlay;
+        }
+        let (editor, level) = match (self.editor.as_mut(), self.level.as_mut()) {
+            (Some(editor), Some(level)) => (editor, level),
+            _ => return,
+        };
+        if keycode == KeyCode::F2 {
+            editor.toggle(self.ship.position);
+            return;
+        }
+        if !editor.active {
+            return;
+        }
+        if Editor::is_save_key(keycode, keymods) {
+            match editor.save(level) {
+                Ok(()) => {
+                    if let Some(watcher) = self.level_watcher.as_mut() {
+                        watcher.reset(level.level_number);
+                    }
+                }
+                Err(e) => println!("Failed to save level: {}", e),
+            }
+        } else if let Err(e) = editor.key_down(ctx, level, keycode) {
+            println!("Editor error: {}", e);
+        }
+    }
+
+    fn mouse_button_down_event(&mut self, ctx: &mut Context, button: MouseButton, x: f32, y: f32) {
+        if let (Some(editor), Some(level)) = (self.editor.as_mut(), self.level.as_ref()) {
+            if editor.active {
+                editor.mouse_button_down(ctx, level, button, x, y);
+            }
+        }
+    }
+
+    fn mouse_button_up_event(&mut self, ctx: &mut Context, button: MouseButton, _x: f32, _y: f32) {
+        if let (Some(editor), Some(level)) = (self.editor.as_mut(), self.level.as_mut()) {
+            if editor.active {
+                if let Err(e) = editor.mouse_button_up(ctx, level, button) {
+                    println!("Editor error: {}", e);
+                }
+            }
+        }
+    }
+
+    fn mouse_motion_event(&mut self, ctx: &mut Context, x: f32, y: f32, _dx: f32, _dy: f32) {
+        if let (Some(editor), Some(level)) = (self.editor.as_mut(), self.level.as_mut()) {
+            if editor.active {
+                if let Err(e) = editor.mouse_motion(ctx, level, x, y) {
+                    println!("Editor error: {}", e);
+                }
+            }
+        }
+    }
+
+    fn mouse_wheel_event(&mut self, _ctx: &mut Context, _x: f32, y: f32) {
+        if let Some(editor) = self.editor.as_mut().filter(|e| e.active) {
+            editor.mouse_wheel(y);
+        }
+    }
 }
 
-#[derive(Deserialize, Debug, Copy, Clone)]
-struct Trigger {
-    id: u32,
-    min_x: f32,
-    max_x: f32,
-    min_y: f32,
-    max_y: f32,
+// Get the rectangles to use as screen coordinates for drawing the world and the UI
+fn get_draw_rects(
+    ctx: &Context,
+    camera_position: Point2<f32>,
+    visible_height: f32,
+) -> (graphics::Rect, graphics::Rect) {
+    let (win_width, win_height) = graphics::drawable_size(ctx);
+    let aspect = if win_height != 0.0 {
+        win_width / win_height
+    } else {
+        1.0
+    };
+    let height = visible_height;
+    let width = height * aspect;
+    let mut world_rect = graphics::Rect::new(-width * 0.5, height * 0.5, width, -height);
+    world_rect.translate(Vector2::new(camera_position.x, camera_position.y));
+
+    let ui_rect = graphics::Rect::new(0.0, 0.0, 800.0, 800.0 * win_height / win_width);
+    (world_rect, ui_rect)
 }
 
-#[derive(Deserialize, Debug)]
-struct RawMeshes {
-    polygons: Vec<Vec<(f32, f32)>>,
-    triggers: Vec<Trigger>,
+// Convert a point in the world to UI coordinates, given the rectangles from get_draw_rects
+fn world_to_ui(
+    point: Point2<f32>,
+    world_rect: graphics::Rect,
+    ui_rect: graphics::Rect,
+) -> Point2<f32> {
+    Point2::new(
+        ui_rect.x + (point.x - world_rect.x) / world_rect.w * ui_rect.w,
+        ui_rect.y + (point.y - world_rect.y) / world_rect.h * ui_rect.h,
+    )
 }
 
 fn load_meshes(_ctx: &mut Context, mut file: File) -> GameResult<RawMeshes> {
     let mut encoded = Vec::<u8>::new();
-    file.read_to_end(&mut encoded).unwrap();
-    let m: RawMeshes = bincode::deserialize(&encoded[..]).unwrap();
-    Ok(m)
+    file.read_to_end(&mut encoded)?;
+    RawMeshes::decode(&encoded[..])
+}
+
+// Bounding rectangle of a polygon, with a margin on all sides
+fn get_bounds(polygon: &[(f32, f32)], margin: f32) -> graphics::Rect {
+    let min_x = polygon.iter().map(|p| p.0).fold(f32::INFINITY, f32::min) - margin;
+    let max_x = polygon
+        .iter()
+        .map(|p| p.0)
+        .fold(f32::NEG_INFINITY, f32::max)
+        + margin;
+    let min_y = polygon.iter().map(|p| p.1).fold(f32::INFINITY, f32::min) - margin;
+    let max_y = polygon
+        .iter()
+        .map(|p| p.1)
+        .fold(f32::NEG_INFINITY, f32::max)
+        + margin;
+    graphics::Rect::new(min_x, min_y, max_x - min_x, max_y - min_y)
 }
 
 fn create_drawables(
     ctx: &mut Context,
-    raw_meshes: &RawMeshes,
+    polygons: &[Vec<(f32, f32)>],
     fill_color: graphics::Color,
     line_color: graphics::Color,
 ) -> GameResult<Vec<graphics::Mesh>> {
     let mut meshes = Vec::<graphics::Mesh>::new();
-    for polygon in raw_meshes.polygons.iter() {
+    for polygon in polygons.iter() {
         let points: Vec<Point2<f32>> = polygon
             .iter()
             .map(|(x, y)| Point2::<f32>::new(*x, *y))
@@ -641,27 +1647,61 @@ fn create_drawables(
 }
 
 pub fn main() -> GameResult {
-    let args: Vec<String> = env::args().collect();
-    let starting_level: u32 = if args.len() == 2 {
-        args[1].parse::<u32>().unwrap_or(1)
-    } else {
-        1
-    };
+    let mut starting_level: Option<u32> = None;
+    let mut dev_mode = false;
+    let mut export_dir: Option<path::PathBuf> = None;
+    let mut language = DEFAULT_LANGUAGE.to_string();
+    let mut new_game = false;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--dev" {
+            // Reload the level when its files change and enable the editor
+            dev_mode = true;
+        } else if arg == "--new" {
+            // Start from the beginning instead of the saved checkpoint
+            new_game = true;
+        } else if arg == "--lang" {
+            // Language of the texts, e.g. "sv"
+            language = args.next().unwrap_or_else(|| DEFAULT_LANGUAGE.to_string());
+        } else if arg == "--export-png" {
+            // Write images of the level to this directory and exit
+            export_dir = Some(path::PathBuf::from(
+                args.next().unwrap_or_else(|| ".".to_string()),
+            ));
+        } else {
+            starting_level = Some(arg.parse::<u32>().unwrap_or(1));
+        }
+    }
 
     let mut builder = ggez::ContextBuilder::new("Ludum Dare 45", "Martin Vilcans");
 
-    if let Ok(manifest_dir) = env::var("CARGO_MANIFEST_DIR") {
-        let base = path::PathBuf::from(manifest_dir.clone());
-        builder = builder
-            .add_resource_path(base.join("resources"))
-            .add_resource_path(base.join("gen-resources"));
+    let resource_dirs = if let Ok(manifest_dir) = env::var("CARGO_MANIFEST_DIR") {
+        let base = path::PathBuf::from(manifest_dir);
+        vec![base.join("resources"), base.join("gen-resources")]
     } else {
-        builder = builder.add_resource_path("./resources");
+        vec![path::PathBuf::from("./resources")]
+    };
+    for dir in resource_dirs.iter() {
+        builder = builder.add_resource_path(dir);
     }
 
     builder = builder.window_setup(conf::WindowSetup::default().title("Ludum Dare 45"));
 
     let (ctx, event_loop) = &mut builder.build()?;
-    let state = &mut MainState::new(ctx, starting_level)?;
+
+    if let Some(dir) = export_dir {
+        let level = load_level(ctx, starting_level.unwrap_or(1))?;
+        let font = graphics::Font::new(ctx, strings::DEFAULT_FONT)?;
+        return export::export_level_images(ctx, &level, font, &dir);
+    }
+
+    let state = &mut MainState::new(
+        ctx,
+        starting_level,
+        resource_dirs,
+        dev_mode,
+        &language,
+        new_game,
+    )?;
     event::run(ctx, event_loop, state)
 }
@@ -0,0 +1,589 @@
+// Mesh and level data as written by bin/convert_mesh.py
+
+use serde::{Deserialize, Serialize};
+
+use ggez::graphics;
+use ggez::{GameError, GameResult};
+
+// Used for levels without an Extents object
+pub const DEFAULT_EXTENTS: Extents = Extents {
+    min_x: -500.0,
+    max_x: 500.0,
+    min_y: -1000.0,
+    max_y: 1000.0,
+    collision_width: 1024,
+    collision_height: 2048,
+};
+
+// Largest supported width or height of the collision map in pixels
+pub const MAX_COLLISION_MAP_SIZE: u32 = 65536;
+
+// The area of the level and the size of its collision map
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub struct Extents {
+    pub min_x: f32,
+    pub max_x: f32,
+    pub min_y: f32,
+    pub max_y: f32,
+    pub collision_width: u32,
+    pub collision_height: u32,
+}
+
+impl Extents {
+    pub fn rect(&self) -> graphics::Rect {
+        graphics::Rect::new(
+            self.min_x,
+            self.min_y,
+            self.max_x - self.min_x,
+            self.max_y - self.min_y,
+        )
+    }
+
+    pub fn validate(&self) -> GameResult {
+        if self.min_x >= self.max_x || self.min_y >= self.max_y {
+            return Err(GameError::ResourceLoadError(format!(
+                "Empty level extents: {:?}",
+                self
+            )));
+        }
+        if self.collision_width == 0
+            || self.collision_height == 0
+            || self.collision_width > MAX_COLLISION_MAP_SIZE
+            || self.collision_height > MAX_COLLISION_MAP_SIZE
+        {
+            return Err(GameError::ResourceLoadError(format!(
+                "Collision map size {}x{} is not between 1 and {}",
+                self.collision_width, self.collision_height, MAX_COLLISION_MAP_SIZE
+            )));
+        }
+        Ok(())
+    }
+}
+
+// Number of line segments in the outline of an ellipse trigger
+const ELLIPSE_SEGMENTS: usize = 32;
+// Points per side of the grid searched for a point inside a polygon trigger
+const INSIDE_POINT_STEPS: usize = 32;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum TriggerShape {
+    Rectangle,
+    // Circle or ellipse filling the rectangle
+    Ellipse,
+    // Vertices relative to the rectangle, from (0, 0) at min_x, min_y
+    // to (1, 1) at max_x, max_y
+    Polygon(Vec<(f32, f32)>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Trigger {
+    pub id: u32,
+    // Bounding rectangle
+    pub min_x: f32,
+    pub max_x: f32,
+    pub min_y: f32,
+    pub max_y: f32,
+    pub shape: TriggerShape,
+    // Whether any of the ship's collider points counts, not just its center
+    pub whole_ship: bool,
+    // When several triggers could fire at once, the highest priority fires
+    // first, then the lowest ID
+    pub priority: i32,
+}
+
+impl Trigger {
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        if !(self.min_x <= x && x < self.max_x && self.min_y <= y && y < self.max_y) {
+            return false;
+        }
+        let u = (x - self.min_x) / (self.max_x - self.min_x);
+        let v = (y - self.min_y) / (self.max_y - self.min_y);
+        match &self.shape {
+            TriggerShape::Rectangle => true,
+            TriggerShape::Ellipse => (u - 0.5).powi(2) + (v - 0.5).powi(2) <= 0.25,
+            TriggerShape::Polygon(polygon) => is_inside_polygon(polygon, u, v),
+        }
+    }
+
+    // The shape in world coordinates, for drawing
+    pub fn get_outline(&self) -> Vec<(f32, f32)> {
+        let to_world = |(u, v): (f32, f32)| {
+            (
+                self.min_x + u * (self.max_x - self.min_x),
+                self.min_y + v * (self.max_y - self.min_y),
+            )
+        };
+        match &self.shape {
+            TriggerShape::Rectangle => vec![
+                (self.min_x, self.min_y),
+                (self.max_x, self.min_y),
+                (self.max_x, self.max_y),
+                (self.min_x, self.max_y),
+            ],
+            TriggerShape::Ellipse => (0..ELLIPSE_SEGMENTS)
+                .map(|i| {
+                    let a = i as f32 / ELLIPSE_SEGMENTS as f32 * std::f32::consts::PI * 2.0;
+                    to_world((0.5 + a.cos() * 0.5, 0.5 + a.sin() * 0.5))
+                })
+                .collect(),
+            TriggerShape::Polygon(polygon) => polygon.iter().copied().map(to_world).collect(),
+        }
+    }
+
+    // A point inside the shape and as far from its edges as possible, e.g.
+    // to respawn at. The middle of a polygon may be outside it, e.g. if it
+    // is L-shaped.
+    pub fn get_inside_point(&self) -> (f32, f32) {
+        let center = (
+            (self.min_x + self.max_x) * 0.5,
+            (self.min_y + self.max_y) * 0.5,
+        );
+        if !matches!(self.shape, TriggerShape::Polygon(_)) {
+            return center;
+        }
+        let outline = self.get_outline();
+        let mut best: Option<(f32, (f32, f32))> = None;
+        for i in 0..INSIDE_POINT_STEPS {
+            for j in 0..INSIDE_POINT_STEPS {
+                let x = self.min_x
+                    + (i as f32 + 0.5) / INSIDE_POINT_STEPS as f32 * (self.max_x - self.min_x);
+                let y = self.min_y
+                    + (j as f32 + 0.5) / INSIDE_POINT_STEPS as f32 * (self.max_y - self.min_y);
+                if !self.contains(x, y) {
+                    continue;
+                }
+                let distance = get_distance_to_outline(&outline, x, y);
+                if best.is_none_or(|(d, _)| distance > d) {
+                    best = Some((distance, (x, y)));
+                }
+            }
+        }
+        best.map_or(center, |(_, point)| point)
+    }
+}
+
+// Distance from a point to the closest edge of a closed polygon
+pub fn get_distance_to_outline(polygon: &[(f32, f32)], x: f32, y: f32) -> f32 {
+    let mut closest = f32::INFINITY;
+    for (i, &(x0, y0)) in polygon.iter().enumerate() {
+        let (x1, y1) = polygon[(i + 1) % polygon.len()];
+        let (dx, dy) = (x1 - x0, y1 - y0);
+        let length_squared = dx * dx + dy * dy;
+        let t = if length_squared == 0.0 {
+            0.0
+        } else {
+            (((x - x0) * dx + (y - y0) * dy) / length_squared).clamp(0.0, 1.0)
+        };
+        let (px, py) = (x0 + dx * t - x, y0 + dy * t - y);
+        closest = closest.min((px * px + py * py).sqrt());
+    }
+    closest
+}
+
+// Something to collect, e.g. a memory fragment
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub struct Pickup {
+    pub id: u32,
+    pub x: f32,
+    pub y: f32,
+    pub radius: f32,
+}
+
+// Overrides of the game's physics constants for a level
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Default)]
+pub struct Physics {
+    pub gravity: Option<f32>,
+    pub thrust: Option<f32>,
+    pub turn_speed: Option<f32>,
+    pub energy_conservation: Option<f32>,
+}
+
+impl Physics {
+    pub fn validate(&self) -> GameResult {
+        if let Some(thrust) = self.thrust.filter(|&t| t.is_nan() || t <= 0.0) {
+            return Err(GameError::ResourceLoadError(format!(
+                "Thrust is {}, must be more than 0",
+                thrust
+            )));
+        }
+        Ok(())
+    }
+}
+
+// An area of the level with its own physics
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Zone {
+    pub polygon: Vec<(f32, f32)>,
+    // Replaces the level's gravity inside the zone
+    pub gravity: Option<(f32, f32)>,
+    // Fraction of the velocity that is lost per second, on top of the level's
+    pub drag: f32,
+    pub thrust_multiplier: f32,
+}
+
+impl Zone {
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        is_inside_polygon(&self.polygon, x, y)
+    }
+
+    // Drag 1 stops the ship at once, and more would make its velocity NaN
+    pub fn validate(&self, index: usize) -> GameResult {
+        if !(0.0..=1.0).contains(&self.drag) {
+            return Err(GameError::ResourceLoadError(format!(
+                "Zone {} has drag {}, must be from 0 to 1",
+                index, self.drag
+            )));
+        }
+        Ok(())
+    }
+}
+
+// Signed area, positive if the polygon is counter-clockwise
+pub fn polygon_area(polygon: &[(f32, f32)]) -> f32 {
+    let mut sum = 0.0;
+    for (i, &(x0, y0)) in polygon.iter().enumerate() {
+        let (x1, y1) = polygon[(i + 1) % polygon.len()];
+        sum += x0 * y1 - x1 * y0;
+    }
+    sum * 0.5
+}
+
+// Even-odd test of whether a point is inside a polygon
+pub fn is_inside_polygon(polygon: &[(f32, f32)], x: f32, y: f32) -> bool {
+    let mut inside = false;
+    for (i, &(x0, y0)) in polygon.iter().enumerate() {
+        let (x1, y1) = polygon[(i + 1) % polygon.len()];
+        if (y0 > y) != (y1 > y) && x < x0 + (y - y0) * (x1 - x0) / (y1 - y0) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum Falloff {
+    // Strength at the radius, falling off with the square of the distance
+    // outside it. Constant inside the radius.
+    InverseSquare,
+    // Strength at the center, falling off to 0 at the radius
+    Linear,
+}
+
+// Point source of gravity, e.g. a planet
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub struct GravityWell {
+    pub x: f32,
+    pub y: f32,
+    // Acceleration towards the well. Negative pushes away.
+    pub strength: f32,
+    pub radius: f32,
+    pub falloff: Falloff,
+}
+
+impl GravityWell {
+    // Acceleration at a position
+    pub fn get_acceleration(&self, x: f32, y: f32) -> (f32, f32) {
+        let dx = self.x - x;
+        let dy = self.y - y;
+        let distance = (dx * dx + dy * dy).sqrt();
+        if distance == 0.0 {
+            return (0.0, 0.0);
+        }
+        let magnitude = match self.falloff {
+            Falloff::InverseSquare => {
+                let r = self.radius / distance.max(self.radius);
+                self.strength * r * r
+            }
+            Falloff::Linear => self.strength * (1.0 - distance / self.radius).max(0.0),
+        };
+        (dx / distance * magnitude, dy / distance * magnitude)
+    }
+}
+
+// An area where wind or a current pushes the ship
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WindField {
+    pub polygon: Vec<(f32, f32)>,
+    // Constant acceleration
+    pub force: (f32, f32),
+    // Largest acceleration added by turbulence, in any direction
+    pub turbulence: f32,
+    // Seed for the turbulence, so fields next to each other differ
+    pub seed: u32,
+}
+
+impl WindField {
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        is_inside_polygon(&self.polygon, x, y)
+    }
+}
+
+// A polygon that moves along a path and/or rotates, e.g. a door or a blade
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Obstacle {
+    // Relative to the pivot
+    pub polygon: Vec<(f32, f32)>,
+    // The pivot moves back and forth between these points, starting at the first
+    pub path: Vec<(f32, f32)>,
+    // Units per second along the path
+    pub speed: f32,
+    // Radians per second, counter-clockwise
+    pub rotation_speed: f32,
+}
+
+impl Obstacle {
+    // Position of the pivot and angle at a time in seconds
+    pub fn get_transform(&self, time: f32) -> ((f32, f32), f32) {
+        let angle = self.rotation_speed * time;
+        let length: f32 = self
+            .path
+            .windows(2)
+            .map(|w| ((w[1].0 - w[0].0).powi(2) + (w[1].1 - w[0].1).powi(2)).sqrt())
+            .sum();
+        let first = match self.path.first() {
+            Some(&p) => p,
+            // Invalid, but rotate around the origin rather than crash
+            None => return ((0.0, 0.0), angle),
+        };
+        if length == 0.0 || self.speed == 0.0 {
+            return (first, angle);
+        }
+        let mut distance = (self.speed * time).abs() % (length * 2.0);
+        if distance > length {
+            // On the way back
+            distance = length * 2.0 - distance;
+        }
+        for w in self.path.windows(2) {
+            let segment = ((w[1].0 - w[0].0).powi(2) + (w[1].1 - w[0].1).powi(2)).sqrt();
+            if distance <= segment && segment != 0.0 {
+                let t = distance / segment;
+                let position = (
+                    w[0].0 + (w[1].0 - w[0].0) * t,
+                    w[0].1 + (w[1].1 - w[0].1) * t,
+                );
+                return (position, angle);
+            }
+            distance -= segment;
+        }
+        (*self.path.last().unwrap_or(&first), angle)
+    }
+
+    pub fn contains(&self, time: f32, x: f32, y: f32) -> bool {
+        let ((px, py), angle) = self.get_transform(time);
+        // Rotate the point into the obstacle's coordinates
+        let (sin, cos) = (-angle).sin_cos();
+        let (dx, dy) = (x - px, y - py);
+        is_inside_polygon(&self.polygon, dx * cos - dy * sin, dx * sin + dy * cos)
+    }
+
+    // Distance from a point to the edge of the obstacle at a time, 0 inside it
+    pub fn get_distance(&self, time: f32, x: f32, y: f32) -> f32 {
+        let ((px, py), angle) = self.get_transform(time);
+        let (sin, cos) = (-angle).sin_cos();
+        let (dx, dy) = (x - px, y - py);
+        let (x, y) = (dx * cos - dy * sin, dx * sin + dy * cos);
+        if is_inside_polygon(&self.polygon, x, y) {
+            0.0
+        } else {
+            get_distance_to_outline(&self.polygon, x, y)
+        }
+    }
+}
+
+// Walls that can be turned on and off by triggers, e.g. a door
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Group {
+    pub name: String,
+    pub polygons: Vec<Vec<(f32, f32)>>,
+    // Whether the walls are there when the level starts
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RawMeshes {
+    pub polygons: Vec<Vec<(f32, f32)>>,
+    pub triggers: Vec<Trigger>,
+    pub extents: Option<Extents>,
+    pub physics: Physics,
+    pub zones: Vec<Zone>,
+    pub wells: Vec<GravityWell>,
+    pub wind: Vec<WindField>,
+    pub obstacles: Vec<Obstacle>,
+    pub groups: Vec<Group>,
+    pub pickups: Vec<Pickup>,
+}
+
+impl RawMeshes {
+    pub fn get_extents(&self) -> Extents {
+        self.extents.unwrap_or(DEFAULT_EXTENTS)
+    }
+
+    // Errors in the data that would crash the game. ld45-levelcheck finds
+    // more problems.
+    pub fn validate(&self) -> GameResult {
+        self.get_extents().validate()?;
+        self.physics.validate()?;
+        for (i, zone) in self.zones.iter().enumerate() {
+            zone.validate(i)?;
+        }
+        for (i, obstacle) in self.obstacles.iter().enumerate() {
+            if obstacle.path.is_empty() {
+                return Err(GameError::ResourceLoadError(format!(
+                    "Obstacle {} has no path",
+                    i
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn decode(encoded: &[u8]) -> GameResult<RawMeshes> {
+        bincode::deserialize(encoded)
+            .map_err(|e| GameError::ResourceLoadError(format!("Invalid mesh data: {}", e)))
+    }
+
+    pub fn encode(&self) -> GameResult<Vec<u8>> {
+        bincode::serialize(self)
+            .map_err(|e| GameError::ResourceLoadError(format!("Could not encode mesh data: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trigger(shape: TriggerShape) -> Trigger {
+        Trigger {
+            id: 1,
+            min_x: 0.0,
+            max_x: 20.0,
+            min_y: 0.0,
+            max_y: 10.0,
+            shape,
+            whole_ship: false,
+            priority: 0,
+        }
+    }
+
+    #[test]
+    fn ellipse_trigger_contains() {
+        let trigger = trigger(TriggerShape::Ellipse);
+        assert!(trigger.contains(10.0, 5.0));
+        assert!(trigger.contains(19.0, 5.0));
+        assert!(trigger.contains(10.0, 9.0));
+        // In the bounding rectangle but outside the ellipse
+        assert!(!trigger.contains(1.0, 1.0));
+        assert!(!trigger.contains(19.0, 9.0));
+        assert!(!trigger.contains(21.0, 5.0));
+    }
+
+    #[test]
+    fn polygon_trigger_contains() {
+        // L-shaped, with the middle of the rectangle outside it
+        let trigger = trigger(TriggerShape::Polygon(vec![
+            (0.0, 0.0),
+            (1.0, 0.0),
+            (1.0, 0.25),
+            (0.25, 0.25),
+            (0.25, 1.0),
+            (0.0, 1.0),
+        ]));
+        assert!(trigger.contains(10.0, 1.0));
+        assert!(trigger.contains(2.0, 8.0));
+        assert!(!trigger.contains(10.0, 5.0));
+        assert!(!trigger.contains(15.0, 8.0));
+
+        let (x, y) = trigger.get_inside_point();
+        assert!(trigger.contains(x, y));
+        // Not right at an edge
+        let outline = trigger.get_outline();
+        assert!(get_distance_to_outline(&outline, x, y) > 1.0);
+    }
+
+    #[test]
+    fn inside_point_of_rectangle_is_middle() {
+        assert_eq!(
+            trigger(TriggerShape::Rectangle).get_inside_point(),
+            (10.0, 5.0)
+        );
+        assert_eq!(
+            trigger(TriggerShape::Ellipse).get_inside_point(),
+            (10.0, 5.0)
+        );
+    }
+
+    fn obstacle() -> Obstacle {
+        Obstacle {
+            polygon: vec![(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)],
+            path: vec![(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)],
+            speed: 5.0,
+            rotation_speed: std::f32::consts::FRAC_PI_2,
+        }
+    }
+
+    fn assert_near(a: (f32, f32), b: (f32, f32)) {
+        assert!(
+            (a.0 - b.0).abs() < 1e-4 && (a.1 - b.1).abs() < 1e-4,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn obstacle_moves_back_and_forth_along_path() {
+        let obstacle = obstacle();
+        assert_near(obstacle.get_transform(0.0).0, (0.0, 0.0));
+        assert_near(obstacle.get_transform(1.0).0, (5.0, 0.0));
+        assert_near(obstacle.get_transform(3.0).0, (10.0, 5.0));
+        assert_near(obstacle.get_transform(4.0).0, (10.0, 10.0));
+        // On the way back
+        assert_near(obstacle.get_transform(5.0).0, (10.0, 5.0));
+        assert_near(obstacle.get_transform(7.0).0, (5.0, 0.0));
+        assert_near(obstacle.get_transform(8.0).0, (0.0, 0.0));
+        assert_near(obstacle.get_transform(9.0).0, (5.0, 0.0));
+    }
+
+    #[test]
+    fn obstacle_rotates_around_pivot() {
+        let mut obstacle = obstacle();
+        obstacle.path = vec![(0.0, 0.0)];
+        obstacle.polygon = vec![(0.0, -0.5), (4.0, -0.5), (4.0, 0.5), (0.0, 0.5)];
+        assert!((obstacle.get_transform(1.0).1 - std::f32::consts::FRAC_PI_2).abs() < 1e-6);
+        assert!(obstacle.contains(0.0, 3.0, 0.0));
+        assert!(!obstacle.contains(0.0, 0.0, 3.0));
+        // A quarter turn counter-clockwise points it up
+        assert!(!obstacle.contains(1.0, 3.0, 0.0));
+        assert!(obstacle.contains(1.0, 0.0, 3.0));
+        assert!((obstacle.get_distance(1.0, 0.0, 6.0) - 2.0).abs() < 1e-4);
+        assert_eq!(obstacle.get_distance(1.0, 0.0, 3.0), 0.0);
+    }
+
+    fn well(falloff: Falloff) -> GravityWell {
+        GravityWell {
+            x: 0.0,
+            y: 0.0,
+            strength: 10.0,
+            radius: 5.0,
+            falloff,
+        }
+    }
+
+    #[test]
+    fn inverse_square_well() {
+        let well = well(Falloff::InverseSquare);
+        // Constant inside the radius, pulling towards the center
+        assert_near(well.get_acceleration(2.0, 0.0), (-10.0, 0.0));
+        assert_near(well.get_acceleration(0.0, -5.0), (0.0, 10.0));
+        assert_near(well.get_acceleration(10.0, 0.0), (-2.5, 0.0));
+        assert_near(well.get_acceleration(0.0, 0.0), (0.0, 0.0));
+    }
+
+    #[test]
+    fn linear_well() {
+        let well = well(Falloff::Linear);
+        assert_near(well.get_acceleration(0.0, 1.0), (0.0, -8.0));
+        assert_near(well.get_acceleration(-2.5, 0.0), (5.0, 0.0));
+        assert_near(well.get_acceleration(6.0, 0.0), (0.0, 0.0));
+    }
+}
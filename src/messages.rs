@@ -0,0 +1,397 @@
+// Texts that pause the game until Return is pressed, shown one page at a time
+// in the order they come. Lines that are too long are wrapped.
+//
+// Markup at the start of a text sets the style of its lines:
+//
+//     [Name]     The speaker, shown before the first line
+//     {#rrggbb}  Color of the text
+//     {i}        Italics, e.g. for memories
+//
+// `\n` is a line break and `\p` starts a new page. Texts with more than
+// MAX_LINES lines are split into pages too.
+
+use std::collections::VecDeque;
+
+use ggez::graphics;
+use ggez::graphics::Color;
+use ggez::nalgebra::{Matrix4, Point2};
+use ggez::{Context, GameResult};
+
+const MAX_LINES: usize = 8;
+// Width of the lines in UI coordinates. The UI is 800 wide.
+const TEXT_WIDTH: f32 = 680.0;
+// Space between the text and the edge of the panel
+pub const PADDING: f32 = 16.0;
+
+const FONT_SIZE: f32 = 28.0;
+const PROMPT_FONT_SIZE: f32 = 18.0;
+const TEXT_COLOR: u32 = 0x00ff00;
+const SPEAKER_COLOR: u32 = 0xffe080;
+const PROMPT_COLOR: u32 = 0x80c080;
+const PANEL_COLOR: u32 = 0x000000;
+const PANEL_ALPHA: f32 = 0.7;
+const PANEL_BORDER_COLOR: u32 = 0x00ff00;
+// Horizontal shift per vertical unit of italic text
+const ITALIC_SLANT: f32 = 0.2;
+
+#[derive(Debug, Clone)]
+struct Line {
+    speaker: Option<String>,
+    text: String,
+    color: Color,
+    italic: bool,
+}
+
+type Page = Vec<Line>;
+
+#[derive(Debug, Clone)]
+struct Style {
+    speaker: Option<String>,
+    color: Color,
+    italic: bool,
+}
+
+// Remove the markup from the start of a text
+fn parse_style(mut text: &str) -> (Style, &str) {
+    let mut style = Style {
+        speaker: None,
+        color: Color::from_rgb_u32(TEXT_COLOR),
+        italic: false,
+    };
+    loop {
+        text = text.trim_start();
+        if let Some(rest) = text.strip_prefix("{i}") {
+            style.italic = true;
+            text = rest;
+        } else if let Some(color) = text
+            .strip_prefix("{#")
+            .and_then(|rest| rest.get(..7))
+            .and_then(|c| c.strip_suffix('}'))
+            .and_then(|c| u32::from_str_radix(c, 16).ok())
+        {
+            style.color = Color::from_rgb_u32(color);
+            text = &text[9..];
+        } else if let Some((speaker, rest)) =
+            text.strip_prefix('[').and_then(|rest| rest.split_once(']'))
+        {
+            style.speaker = Some(speaker.trim().to_string());
+            text = rest;
+        } else {
+            return (style, text);
+        }
+    }
+}
+
+// Split a text into lines that fit a width. Words that are too long on their
+// own, e.g. in scripts without spaces, are split between characters.
+pub fn wrap(
+    measure: &mut dyn FnMut(&str) -> f32,
+    text: &str,
+    first_width: f32,
+    width: f32,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let max_width = |lines: &Vec<String>| if lines.is_empty() { first_width } else { width };
+    for word in text.split(' ') {
+        let candidate = if line.is_empty() {
+            word.to_string()
+        } else {
+            format!("{} {}", line, word)
+        };
+        if measure(&candidate) <= max_width(&lines) {
+            line = candidate;
+            continue;
+        }
+        if !line.is_empty() {
+            lines.push(std::mem::take(&mut line));
+        }
+        for c in word.chars() {
+            line.push(c);
+            if line.chars().count() > 1 && measure(&line) > max_width(&lines) {
+                line.pop();
+                lines.push(std::mem::replace(&mut line, c.to_string()));
+            }
+        }
+    }
+    lines.push(line);
+    lines
+}
+
+// A semi-transparent panel with a border for UI to be drawn on
+pub fn draw_panel(ctx: &mut Context, rect: graphics::Rect) -> GameResult {
+    let mut panel_color = Color::from_rgb_u32(PANEL_COLOR);
+    panel_color.a = PANEL_ALPHA;
+    let mesh = graphics::MeshBuilder::new()
+        .rectangle(
+            graphics::DrawMode::Fill(graphics::FillOptions::default()),
+            rect,
+            panel_color,
+        )
+        .rectangle(
+            graphics::DrawMode::Stroke(graphics::StrokeOptions::default()),
+            rect,
+            Color::from_rgb_u32(PANEL_BORDER_COLOR),
+        )
+        .build(ctx)?;
+    graphics::draw(ctx, &mesh, graphics::DrawParam::default())
+}
+
+// Split texts into pages of wrapped lines
+fn create_pages(measure: &mut dyn FnMut(&str) -> f32, texts: &[&str]) -> Vec<Page> {
+    let mut pages = Vec::new();
+    let mut page = Page::new();
+    for source in texts.iter() {
+        let (style, source) = parse_style(source);
+        for (page_index, page_source) in source.split("\\p").enumerate() {
+            if page_index != 0 && !page.is_empty() {
+                pages.push(std::mem::take(&mut page));
+            }
+            for (line_index, text) in page_source.trim().split('\n').enumerate() {
+                // The speaker goes before the first line only
+                let speaker = style
+                    .speaker
+                    .clone()
+                    .filter(|_| page_index == 0 && line_index == 0);
+                let speaker_width = speaker
+                    .as_ref()
+                    .map_or(0.0, |s| measure(&format!("{}: ", s)));
+                let wrapped = wrap(measure, text, TEXT_WIDTH - speaker_width, TEXT_WIDTH);
+                for (wrap_index, text) in wrapped.into_iter().enumerate() {
+                    if page.len() == MAX_LINES {
+                        pages.push(std::mem::take(&mut page));
+                    }
+                    page.push(Line {
+                        speaker: speaker.clone().filter(|_| wrap_index == 0),
+                        text,
+                        color: style.color,
+                        italic: style.italic,
+                    });
+                }
+            }
+        }
+    }
+    if !page.is_empty() {
+        pages.push(page);
+    }
+    pages
+}
+
+pub struct Messages {
+    pages: VecDeque<Page>,
+    font: graphics::Font,
+}
+
+impl Messages {
+    pub fn new(font: graphics::Font) -> Messages {
+        Messages {
+            pages: VecDeque::new(),
+            font,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    // Add a message made of texts, each with its own markup
+    pub fn push(&mut self, ctx: &mut Context, texts: &[&str]) {
+        let font = self.font;
+        let mut measure = |text: &str| {
+            let mut t = graphics::Text::new(text);
+            t.set_font(font, graphics::Scale::uniform(FONT_SIZE));
+            t.width(ctx) as f32
+        };
+        self.pages.extend(create_pages(&mut measure, texts));
+    }
+
+    // Go to the next page. Returns true if there are no more.
+    pub fn advance(&mut self) -> bool {
+        self.pages.pop_front();
+        self.pages.is_empty()
+    }
+
+    // Draw the current page in a panel in the middle of the UI
+    pub fn draw(&self, ctx: &mut Context, ui_rect: graphics::Rect, prompt: &str) -> GameResult {
+        let page = match self.pages.front() {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        let scale = graphics::Scale::uniform(FONT_SIZE);
+        let texts: Vec<(graphics::Text, bool)> = page
+            .iter()
+            .map(|line| {
+                let mut text = graphics::Text::default();
+                if let Some(speaker) = &line.speaker {
+                    text.add(
+                        graphics::TextFragment::new(format!("{}: ", speaker))
+                            .color(Color::from_rgb_u32(SPEAKER_COLOR))
+                            .font(self.font)
+                            .scale(scale),
+                    );
+                }
+                // Keep empty lines as high as the others
+                let content = if line.text.is_empty() {
+                    " "
+                } else {
+                    &line.text
+                };
+                text.add(
+                    graphics::TextFragment::new(content)
+                        .color(line.color)
+                        .font(self.font)
+                        .scale(scale),
+                );
+                (text, line.italic)
+            })
+            .collect();
+        let mut prompt = graphics::Text::new(prompt);
+        prompt.set_font(self.font, graphics::Scale::uniform(PROMPT_FONT_SIZE));
+
+        let (prompt_width, prompt_height) = prompt.dimensions(ctx);
+        let mut text_width = prompt_width as f32;
+        let mut text_height = prompt_height as f32;
+        for (text, _) in texts.iter() {
+            let (width, height) = text.dimensions(ctx);
+            text_width = text_width.max(width as f32);
+            text_height += height as f32;
+        }
+        let panel = graphics::Rect::new(
+            ui_rect.x + (ui_rect.w - text_width) * 0.5 - PADDING,
+            ui_rect.y + (ui_rect.h - text_height) * 0.5 - PADDING,
+            text_width + PADDING * 2.0,
+            text_height + PADDING * 2.0,
+        );
+        draw_panel(ctx, panel)?;
+
+        let x = panel.x + PADDING;
+        let mut y = panel.y + PADDING;
+        for (text, italic) in texts.iter() {
+            let height = text.height(ctx) as f32;
+            if *italic {
+                // Slant around the bottom of the line
+                #[rustfmt::skip]
+                let transform = Matrix4::new(
+                    1.0, -ITALIC_SLANT, 0.0, x + ITALIC_SLANT * height,
+                    0.0, 1.0, 0.0, y,
+                    0.0, 0.0, 1.0, 0.0,
+                    0.0, 0.0, 0.0, 1.0,
+                );
+                graphics::push_transform(ctx, Some(transform));
+                graphics::apply_transformations(ctx)?;
+                graphics::draw(ctx, text, graphics::DrawParam::default())?;
+                graphics::pop_transform(ctx);
+                graphics::apply_transformations(ctx)?;
+            } else {
+                graphics::draw(
+                    ctx,
+                    text,
+                    graphics::DrawParam::default().dest(Point2::new(x, y)),
+                )?;
+            }
+            y += height;
+        }
+        graphics::draw(
+            ctx,
+            &prompt,
+            graphics::DrawParam::default()
+                .dest(Point2::new(
+                    panel.right() - PADDING - prompt_width as f32,
+                    y,
+                ))
+                .color(Color::from_rgb_u32(PROMPT_COLOR)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every character is 10 wide, so TEXT_WIDTH fits 68
+    fn measure(text: &str) -> f32 {
+        text.chars().count() as f32 * 10.0
+    }
+
+    fn get_texts(page: &Page) -> Vec<&str> {
+        page.iter().map(|line| line.text.as_str()).collect()
+    }
+
+    #[test]
+    fn parse_style_reads_markup() {
+        let (style, text) = parse_style("[Mom] {#ff0000}{i} Hello [there]");
+        assert_eq!(style.speaker.as_deref(), Some("Mom"));
+        assert_eq!(style.color, Color::from_rgb_u32(0xff0000));
+        assert!(style.italic);
+        assert_eq!(text, "Hello [there]");
+    }
+
+    #[test]
+    fn parse_style_keeps_invalid_markup() {
+        let (style, text) = parse_style("{#ff00zz}Hello");
+        assert_eq!(style.speaker, None);
+        assert_eq!(style.color, Color::from_rgb_u32(TEXT_COLOR));
+        assert!(!style.italic);
+        assert_eq!(text, "{#ff00zz}Hello");
+
+        let (style, text) = parse_style("[Unclosed Hello");
+        assert_eq!(style.speaker, None);
+        assert_eq!(text, "[Unclosed Hello");
+    }
+
+    #[test]
+    fn wrap_breaks_between_words() {
+        assert_eq!(
+            wrap(&mut measure, "aaa bbb ccc", 70.0, 70.0),
+            vec!["aaa bbb", "ccc"]
+        );
+        assert_eq!(wrap(&mut measure, "", 70.0, 70.0), vec![""]);
+    }
+
+    #[test]
+    fn wrap_uses_first_width_for_first_line() {
+        assert_eq!(
+            wrap(&mut measure, "aaa bbb ccc", 30.0, 70.0),
+            vec!["aaa", "bbb ccc"]
+        );
+    }
+
+    #[test]
+    fn wrap_splits_long_words() {
+        assert_eq!(
+            wrap(&mut measure, "aa bbbbbbbbbb c", 40.0, 40.0),
+            vec!["aa", "bbbb", "bbbb", "bb c"]
+        );
+        // A character wider than the line still gets a line of its own
+        assert_eq!(wrap(&mut measure, "abc", 5.0, 5.0), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn create_pages_splits_at_page_breaks() {
+        let pages = create_pages(&mut measure, &["[Mom] One\\p Two\nThree", "Four"]);
+        assert_eq!(pages.len(), 2);
+        assert_eq!(get_texts(&pages[0]), vec!["One"]);
+        assert_eq!(get_texts(&pages[1]), vec!["Two", "Three", "Four"]);
+        assert_eq!(pages[0][0].speaker.as_deref(), Some("Mom"));
+        assert!(pages[1].iter().all(|line| line.speaker.is_none()));
+    }
+
+    #[test]
+    fn create_pages_splits_long_texts() {
+        let lines: Vec<String> = (0..MAX_LINES + 2).map(|i| i.to_string()).collect();
+        let pages = create_pages(&mut measure, &[&lines.join("\n")]);
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].len(), MAX_LINES);
+        assert_eq!(get_texts(&pages[1]), lines[MAX_LINES..].to_vec());
+    }
+
+    #[test]
+    fn create_pages_wraps_after_speaker() {
+        let text = format!("[Me] {}", ["word"; 20].join(" "));
+        let pages = create_pages(&mut measure, &[&text]);
+        // "Me: " takes 4 characters of the first line
+        assert_eq!(pages[0][0].text.chars().count(), 64);
+        assert_eq!(pages[0][0].speaker.as_deref(), Some("Me"));
+        assert_eq!(pages[0][1].speaker, None);
+    }
+}
@@ -0,0 +1,78 @@
+// Music tracks that loop, and crossfading from one to another.
+
+use ggez::audio;
+use ggez::audio::SoundSource;
+use ggez::Context;
+
+pub const DEFAULT_TRACK: &str = "/music.ogg";
+// Crossfade duration in seconds when the script doesn't say
+pub const DEFAULT_CROSSFADE: f32 = 2.0;
+
+struct Track {
+    name: String,
+    source: audio::Source,
+    // From 0 (silent) to 1 (full volume)
+    fade: f32,
+    // Change of fade per second
+    fade_speed: f32,
+}
+
+pub struct Music {
+    current: Option<Track>,
+    // Tracks that are fading out
+    previous: Vec<Track>,
+}
+
+impl Music {
+    pub fn new() -> Music {
+        Music {
+            current: None,
+            previous: Vec::new(),
+        }
+    }
+
+    // Fade to a track over a number of seconds. Does nothing if it is
+    // already playing.
+    pub fn play(&mut self, ctx: &mut Context, name: &str, duration: f32) {
+        if self.current.as_ref().is_some_and(|t| t.name == name) {
+            return;
+        }
+        let mut source = match audio::Source::new(ctx, name) {
+            Ok(s) => s,
+            Err(e) => {
+                println!("Failed to load music {}: {}", name, e);
+                return;
+            }
+        };
+        source.set_repeat(true);
+        source.set_volume(0.0);
+        if let Err(e) = source.play() {
+            println!("Failed to play music {}: {}", name, e);
+            return;
+        }
+        // Immediately, i.e. within a frame, if the duration is 0
+        let fade_speed = 1.0 / duration.max(0.001);
+        if let Some(mut track) = self.current.take() {
+            track.fade_speed = -fade_speed;
+            self.previous.push(track);
+        }
+        // If this track was fading out, start from scratch instead
+        self.previous.retain(|t| t.name != name);
+        self.current = Some(Track {
+            name: name.to_string(),
+            source,
+            fade: 0.0,
+            fade_speed,
+        });
+    }
+
+    // volume is the music volume from the settings
+    pub fn update(&mut self, delta_time: f32, volume: f32) {
+        for track in self.current.iter_mut().chain(self.previous.iter_mut()) {
+            track.fade = (track.fade + track.fade_speed * delta_time).clamp(0.0, 1.0);
+            track.source.set_volume(track.fade * volume);
+        }
+        // Dropping a source stops it
+        self.previous.retain(|t| t.fade > 0.0);
+    }
+}
@@ -0,0 +1,175 @@
+// The player's progress, saved in the user directory so it is kept when the
+// game is restarted.
+//
+// The file is text with one entry per line:
+//
+//     collected <level> <pickup id>
+//     checkpoint <level> <x> <y> <angle> <thrust enabled> <turning enabled>
+
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+
+use ggez::filesystem;
+use ggez::{Context, GameResult};
+
+const PROGRESS_FILE: &str = "/progress.txt";
+
+// Where the ship respawns
+#[derive(Debug, Copy, Clone)]
+pub struct Checkpoint {
+    pub level_number: u32,
+    pub x: f32,
+    pub y: f32,
+    pub angle: f32,
+    pub thrust_enabled: bool,
+    pub turning_enabled: bool,
+}
+
+impl Checkpoint {
+    fn parse(parts: &[&str]) -> Option<Checkpoint> {
+        match parts {
+            [level, x, y, angle, thrust, turning] => Some(Checkpoint {
+                level_number: level.parse().ok()?,
+                x: x.parse().ok()?,
+                y: y.parse().ok()?,
+                angle: angle.parse().ok()?,
+                thrust_enabled: *thrust == "1",
+                turning_enabled: *turning == "1",
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Progress {
+    // Pickup IDs per level
+    collected: HashMap<u32, HashSet<u32>>,
+    pub checkpoint: Option<Checkpoint>,
+}
+
+impl Progress {
+    // Read the saved progress. Starts from scratch if there is none.
+    pub fn load(ctx: &mut Context) -> Progress {
+        if !filesystem::exists(ctx, PROGRESS_FILE) {
+            return Progress::default();
+        }
+        let mut source = String::new();
+        if let Err(e) = filesystem::open(ctx, PROGRESS_FILE)
+            .and_then(|mut f| Ok(f.read_to_string(&mut source)?))
+        {
+            println!("Failed to read progress: {}", e);
+            return Progress::default();
+        }
+        Progress::parse(&source)
+    }
+
+    pub fn save(&self, ctx: &mut Context) -> GameResult {
+        let mut f = filesystem::create(ctx, PROGRESS_FILE)?;
+        f.write_all(self.format().as_bytes())?;
+        Ok(())
+    }
+
+    // Invalid lines are skipped
+    fn parse(source: &str) -> Progress {
+        let mut progress = Progress::default();
+        for line in source.lines() {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            match parts[..] {
+                ["collected", level, id] => match (level.parse(), id.parse()) {
+                    (Ok(level), Ok(id)) => progress.collect(level, id),
+                    _ => println!("Invalid line in progress: {}", line),
+                },
+                ["checkpoint", ref rest @ ..] => match Checkpoint::parse(rest) {
+                    Some(checkpoint) => progress.checkpoint = Some(checkpoint),
+                    None => println!("Invalid line in progress: {}", line),
+                },
+                [] => {}
+                _ => println!("Invalid line in progress: {}", line),
+            }
+        }
+        progress
+    }
+
+    fn format(&self) -> String {
+        let mut levels: Vec<&u32> = self.collected.keys().collect();
+        levels.sort_unstable();
+        let mut out = String::new();
+        for level in levels {
+            let mut ids: Vec<&u32> = self.collected[level].iter().collect();
+            ids.sort_unstable();
+            for id in ids {
+                out += &format!("collected {} {}\n", level, id);
+            }
+        }
+        if let Some(c) = self.checkpoint {
+            out += &format!(
+                "checkpoint {} {} {} {} {} {}\n",
+                c.level_number, c.x, c.y, c.angle, c.thrust_enabled as u8, c.turning_enabled as u8
+            );
+        }
+        out
+    }
+
+    pub fn collect(&mut self, level_number: u32, pickup_id: u32) {
+        self.collected
+            .entry(level_number)
+            .or_default()
+            .insert(pickup_id);
+    }
+
+    pub fn is_collected(&self, level_number: u32, pickup_id: u32) -> bool {
+        self.collected
+            .get(&level_number)
+            .is_some_and(|ids| ids.contains(&pickup_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_round_trip() {
+        let mut progress = Progress::default();
+        progress.collect(2, 5);
+        progress.collect(2, 1);
+        progress.collect(10, 3);
+        progress.checkpoint = Some(Checkpoint {
+            level_number: 2,
+            x: -12.5,
+            y: 300.0,
+            angle: 1.5,
+            thrust_enabled: true,
+            turning_enabled: false,
+        });
+        let source = progress.format();
+        assert_eq!(
+            source,
+            "collected 2 1\n\
+             collected 2 5\n\
+             collected 10 3\n\
+             checkpoint 2 -12.5 300 1.5 1 0\n"
+        );
+
+        let loaded = Progress::parse(&source);
+        assert!(loaded.is_collected(2, 1));
+        assert!(loaded.is_collected(2, 5));
+        assert!(loaded.is_collected(10, 3));
+        assert!(!loaded.is_collected(10, 5));
+        assert_eq!(loaded.format(), source);
+    }
+
+    #[test]
+    fn invalid_lines_are_skipped() {
+        let progress = Progress::parse(
+            "collected 1\n\
+             collected 1 2\n\
+             checkpoint 1 2 3\n\
+             unknown\n\
+             \n",
+        );
+        assert!(progress.is_collected(1, 2));
+        assert!(progress.checkpoint.is_none());
+    }
+}
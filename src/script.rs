@@ -0,0 +1,374 @@
+// Trigger scripts.
+//
+// Each level has a script file (`/levelNN.script`) that says what happens
+// when the ship enters a trigger. Every non-empty line that isn't a comment
+// has the form
+//
+//     <trigger id> <command> [argument]
+//
+// and the commands for a trigger are executed in the order they appear.
+//
+// Commands:
+//
+//     say <text>         Show a text. `\n` in the text is a line break.
+//                        The game is paused until Return is pressed.
+//                        See src/messages.rs for speakers, colors and pages.
+//     subtitle <text>    Show a text at the bottom of the screen without
+//                        pausing the game. It goes away by itself.
+//     enable <ability>   Enable `thrust` or `turning`.
+//     level <number>     Go to this level when the text is dismissed.
+//     end                The story is over. When the text is dismissed, the
+//                        saved checkpoint is forgotten so the next game
+//                        starts from the beginning.
+//     show <group>       Turn on a group of walls, e.g. close a door.
+//     hide <group>       Turn off a group of walls, e.g. open a door.
+//     toggle <group>     Turn a group of walls on if it's off and vice versa.
+//     checkpoint [angle] Respawn inside the trigger, as far from its edges
+//                        as possible, pointing in this direction in degrees
+//                        (default 90, i.e. up), with the abilities the ship
+//                        has now.
+//     music <file> [s]   Crossfade to a music track, e.g. `/music.ogg`, over
+//                        s seconds (default 2). On trigger 0, it is the
+//                        level's music, which starts with the level.
+//     require <n> <text> Unless n pickups (or `all`) on the level have been
+//                        collected, show the text and skip the rest of the
+//                        commands. The trigger then works again next time
+//                        the ship enters it.
+//
+// Options, which apply to the trigger whatever line they are on:
+//
+//     repeat [seconds]   Fire every time the ship enters, but not again until
+//                        this many seconds (default 0) have passed.
+//     when exit          Fire when the ship leaves instead of enters.
+//     when thrusting     Only fire while the ship is thrusting.
+//     when coasting      Only fire while the ship is not thrusting.
+//     when visited <id>  Only fire if trigger <id> has fired before.
+//     when slower <n>    Only fire if the ship's speed is below n.
+//
+// If a condition doesn't hold when the ship enters the trigger, the trigger
+// fires as soon as it does, as long as the ship is still inside.
+
+use std::collections::HashMap;
+
+use ggez::{GameError, GameResult};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Ability {
+    Thrust,
+    Turning,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Say(String),
+    Subtitle(String),
+    Enable(Ability),
+    GotoLevel(u32),
+    // The story is over
+    End,
+    ShowGroup(String),
+    HideGroup(String),
+    ToggleGroup(String),
+    // None means all pickups on the level
+    Require(Option<u32>, String),
+    // Angle in radians
+    Checkpoint(f32),
+    // Track and crossfade duration in seconds
+    Music(String, Option<f32>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TriggerOptions {
+    // Seconds before the trigger can fire again. None means it fires once.
+    pub repeat: Option<f32>,
+    pub on_exit: bool,
+    // Whether the ship must be thrusting or not, if it matters
+    pub thrusting: Option<bool>,
+    // Triggers that must have fired before
+    pub visited: Vec<u32>,
+    pub max_speed: Option<f32>,
+}
+
+static DEFAULT_OPTIONS: TriggerOptions = TriggerOptions {
+    repeat: None,
+    on_exit: false,
+    thrusting: None,
+    visited: Vec::new(),
+    max_speed: None,
+};
+
+#[derive(Debug, Default)]
+pub struct Script {
+    actions: HashMap<u32, Vec<Action>>,
+    options: HashMap<u32, TriggerOptions>,
+}
+
+impl Script {
+    pub fn parse(source: &str) -> GameResult<Script> {
+        let mut actions = HashMap::<u32, Vec<Action>>::new();
+        let mut options = HashMap::<u32, TriggerOptions>::new();
+        for (line_index, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| {
+                GameError::ResourceLoadError(format!(
+                    "Script line {}: {}: {}",
+                    line_index + 1,
+                    message,
+                    line
+                ))
+            };
+            let mut parts = line.splitn(3, ' ');
+            let trigger_id = parts
+                .next()
+                .unwrap()
+                .parse::<u32>()
+                .map_err(|_| error("Invalid trigger ID"))?;
+            let command = parts.next().ok_or_else(|| error("Missing command"))?;
+            let argument = parts.next().unwrap_or("").trim();
+            if command == "repeat" || command == "when" {
+                let o = options
+                    .entry(trigger_id)
+                    .or_insert_with(|| DEFAULT_OPTIONS.clone());
+                if command == "repeat" {
+                    o.repeat = Some(if argument.is_empty() {
+                        0.0
+                    } else {
+                        argument
+                            .parse::<f32>()
+                            .map_err(|_| error("Invalid number of seconds"))?
+                    });
+                    continue;
+                }
+                let mut parts = argument.splitn(2, ' ');
+                let condition = parts.next().unwrap();
+                let value = parts.next().unwrap_or("").trim();
+                match condition {
+                    "exit" => o.on_exit = true,
+                    "thrusting" => o.thrusting = Some(true),
+                    "coasting" => o.thrusting = Some(false),
+                    "visited" => o.visited.push(
+                        value
+                            .parse::<u32>()
+                            .map_err(|_| error("Invalid trigger ID"))?,
+                    ),
+                    "slower" => {
+                        o.max_speed =
+                            Some(value.parse::<f32>().map_err(|_| error("Invalid speed"))?)
+                    }
+                    _ => return Err(error("Unknown condition")),
+                }
+                continue;
+            }
+            let action = match command {
+                "say" => Action::Say(argument.replace("\\n", "\n")),
+                "subtitle" => Action::Subtitle(argument.replace("\\n", "\n")),
+                "enable" => Action::Enable(match argument {
+                    "thrust" => Ability::Thrust,
+                    "turning" => Ability::Turning,
+                    _ => return Err(error("Unknown ability")),
+                }),
+                "level" => Action::GotoLevel(
+                    argument
+                        .parse::<u32>()
+                        .map_err(|_| error("Invalid level number"))?,
+                ),
+                "end" => Action::End,
+                "show" | "hide" | "toggle" if argument.is_empty() => {
+                    return Err(error("Missing group name"))
+                }
+                "show" => Action::ShowGroup(argument.to_string()),
+                "hide" => Action::HideGroup(argument.to_string()),
+                "toggle" => Action::ToggleGroup(argument.to_string()),
+                "checkpoint" => Action::Checkpoint(if argument.is_empty() {
+                    std::f32::consts::FRAC_PI_2
+                } else {
+                    argument
+                        .parse::<f32>()
+                        .map_err(|_| error("Invalid angle"))?
+                        .to_radians()
+                }),
+                "music" => {
+                    let mut parts = argument.split_whitespace();
+                    let track = parts.next().ok_or_else(|| error("Missing music file"))?;
+                    let duration = match parts.next() {
+                        Some(s) => Some(
+                            s.parse::<f32>()
+                                .map_err(|_| error("Invalid number of seconds"))?,
+                        ),
+                        None => None,
+                    };
+                    Action::Music(track.to_string(), duration)
+                }
+                "require" => {
+                    let mut parts = argument.splitn(2, ' ');
+                    let count = match parts.next().unwrap() {
+                        "all" => None,
+                        n => Some(
+                            n.parse::<u32>()
+                                .map_err(|_| error("Invalid number of pickups"))?,
+                        ),
+                    };
+                    let text = parts.next().unwrap_or("").trim().replace("\\n", "\n");
+                    Action::Require(count, text)
+                }
+                _ => return Err(error("Unknown command")),
+            };
+            actions.entry(trigger_id).or_default().push(action);
+        }
+        Ok(Script { actions, options })
+    }
+
+    pub fn get_actions(&self, trigger_id: u32) -> Option<&[Action]> {
+        self.actions.get(&trigger_id).map(|a| &a[..])
+    }
+
+    pub fn get_options(&self, trigger_id: u32) -> &TriggerOptions {
+        self.options.get(&trigger_id).unwrap_or(&DEFAULT_OPTIONS)
+    }
+
+    // The music of the level, i.e. the last track of trigger 0
+    pub fn get_level_music(&self) -> Option<(&str, Option<f32>)> {
+        self.get_actions(0)?.iter().rev().find_map(|a| match a {
+            Action::Music(track, duration) => Some((track.as_str(), *duration)),
+            _ => None,
+        })
+    }
+
+    // All actions of all triggers
+    pub fn get_all_actions(&self) -> impl Iterator<Item = (u32, &Action)> + '_ {
+        self.actions
+            .iter()
+            .flat_map(|(&id, actions)| actions.iter().map(move |a| (id, a)))
+    }
+
+    // IDs of all triggers that have actions
+    pub fn get_trigger_ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.actions.keys().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(source: &str) -> String {
+        match Script::parse(source) {
+            Err(GameError::ResourceLoadError(message)) => message,
+            other => panic!("Expected an error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_actions() {
+        let script = Script::parse(
+            "# Comment\n\n\
+             10 say Hello\\nthere\n\
+             10 enable thrust\n\
+             11 show door\n\
+             11 hide wall\n\
+             11 toggle gate\n",
+        )
+        .unwrap();
+        assert_eq!(
+            script.get_actions(10).unwrap(),
+            &[
+                Action::Say("Hello\nthere".to_string()),
+                Action::Enable(Ability::Thrust),
+            ]
+        );
+        assert_eq!(
+            script.get_actions(11).unwrap(),
+            &[
+                Action::ShowGroup("door".to_string()),
+                Action::HideGroup("wall".to_string()),
+                Action::ToggleGroup("gate".to_string()),
+            ]
+        );
+        assert_eq!(script.get_actions(12), None);
+    }
+
+    #[test]
+    fn parse_require() {
+        let script = Script::parse("1 require all Not yet\\nsorry\n2 require 3\n").unwrap();
+        assert_eq!(
+            script.get_actions(1).unwrap(),
+            &[Action::Require(None, "Not yet\nsorry".to_string())]
+        );
+        assert_eq!(
+            script.get_actions(2).unwrap(),
+            &[Action::Require(Some(3), String::new())]
+        );
+    }
+
+    #[test]
+    fn parse_checkpoint_and_end() {
+        let script = Script::parse("1 checkpoint\n2 checkpoint 180\n3 end\n").unwrap();
+        assert_eq!(
+            script.get_actions(1).unwrap(),
+            &[Action::Checkpoint(std::f32::consts::FRAC_PI_2)]
+        );
+        assert_eq!(
+            script.get_actions(2).unwrap(),
+            &[Action::Checkpoint(std::f32::consts::PI)]
+        );
+        assert_eq!(script.get_actions(3).unwrap(), &[Action::End]);
+    }
+
+    #[test]
+    fn parse_options() {
+        let script = Script::parse(
+            "1 repeat\n\
+             2 repeat 2.5\n\
+             2 when exit\n\
+             3 when thrusting\n\
+             3 when visited 1\n\
+             3 when visited 2\n\
+             4 when coasting\n\
+             4 when slower 50\n",
+        )
+        .unwrap();
+        assert_eq!(script.get_options(1).repeat, Some(0.0));
+        assert_eq!(script.get_options(2).repeat, Some(2.5));
+        assert!(script.get_options(2).on_exit);
+        assert_eq!(script.get_options(3).thrusting, Some(true));
+        assert_eq!(script.get_options(3).visited, vec![1, 2]);
+        assert_eq!(script.get_options(4).thrusting, Some(false));
+        assert_eq!(script.get_options(4).max_speed, Some(50.0));
+        // Options don't count as actions
+        assert_eq!(script.get_actions(1), None);
+        assert_eq!(script.get_options(5), &DEFAULT_OPTIONS);
+    }
+
+    #[test]
+    fn level_music_is_last_track_of_trigger_0() {
+        let script = Script::parse("0 music /a.ogg\n0 music /b.ogg 1\n1 music /c.ogg").unwrap();
+        assert_eq!(script.get_level_music(), Some(("/b.ogg", Some(1.0))));
+        assert_eq!(
+            script.get_actions(1).unwrap(),
+            &[Action::Music("/c.ogg".to_string(), None)]
+        );
+        assert_eq!(Script::parse("1 say Hi").unwrap().get_level_music(), None);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse_error("1 say Hi\nx say Hi").starts_with("Script line 2: Invalid trigger ID"));
+        assert!(parse_error("1").contains("Missing command"));
+        assert!(parse_error("1 fly").contains("Unknown command"));
+        assert!(parse_error("1 enable flying").contains("Unknown ability"));
+        assert!(parse_error("1 show").contains("Missing group name"));
+        assert!(parse_error("1 toggle").contains("Missing group name"));
+        assert!(parse_error("1 repeat soon").contains("Invalid number of seconds"));
+        assert!(parse_error("1 when").contains("Unknown condition"));
+        assert!(parse_error("1 when raining").contains("Unknown condition"));
+        assert!(parse_error("1 when visited").contains("Invalid trigger ID"));
+        assert!(parse_error("1 when slower fast").contains("Invalid speed"));
+        assert!(parse_error("1 music").contains("Missing music file"));
+        assert!(parse_error("1 music /a.ogg soon").contains("Invalid number of seconds"));
+        assert!(parse_error("1 checkpoint up").contains("Invalid angle"));
+        assert!(parse_error("1 require some Not yet").contains("Invalid number of pickups"));
+    }
+}
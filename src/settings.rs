@@ -0,0 +1,282 @@
+// Settings, saved in the user directory like the progress.
+//
+// The file is text with one setting per line:
+//
+//     master 1
+//     music 0.8
+//     effects 1
+//     interface 1
+//     muted 0
+//
+// Volumes are from 0 to 1. F1 opens a menu where they are changed, and M
+// mutes all sound.
+
+use std::io::{Read, Write};
+
+use ggez::filesystem;
+use ggez::graphics;
+use ggez::graphics::Color;
+use ggez::input::keyboard::KeyCode;
+use ggez::nalgebra::Point2;
+use ggez::{Context, GameResult};
+
+use crate::messages::{draw_panel, PADDING};
+use crate::strings::Strings;
+
+const SETTINGS_FILE: &str = "/settings.txt";
+
+// How much a volume changes per key press
+const VOLUME_STEP: f32 = 0.1;
+
+const FONT_SIZE: f32 = 28.0;
+const TEXT_COLOR: u32 = 0x00ff00;
+const SELECTED_COLOR: u32 = 0xffe080;
+// Space between the name of a setting and its value
+const VALUE_COLUMN: f32 = 240.0;
+
+// What a sound is, for its volume
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Channel {
+    Music,
+    Effects,
+    // Sounds of the texts
+    Interface,
+}
+
+#[derive(Debug)]
+pub struct Settings {
+    master: f32,
+    music: f32,
+    effects: f32,
+    interface: f32,
+    pub muted: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            master: 1.0,
+            music: 1.0,
+            effects: 1.0,
+            interface: 1.0,
+            muted: false,
+        }
+    }
+}
+
+impl Settings {
+    // Read the saved settings, or use the defaults if there are none
+    pub fn load(ctx: &mut Context) -> Settings {
+        if !filesystem::exists(ctx, SETTINGS_FILE) {
+            return Settings::default();
+        }
+        let mut source = String::new();
+        if let Err(e) = filesystem::open(ctx, SETTINGS_FILE)
+            .and_then(|mut f| Ok(f.read_to_string(&mut source)?))
+        {
+            println!("Failed to read settings: {}", e);
+            return Settings::default();
+        }
+        Settings::parse(&source)
+    }
+
+    pub fn save(&self, ctx: &mut Context) -> GameResult {
+        let mut f = filesystem::create(ctx, SETTINGS_FILE)?;
+        f.write_all(self.format().as_bytes())?;
+        Ok(())
+    }
+
+    // Invalid lines are skipped and volumes are clamped
+    fn parse(source: &str) -> Settings {
+        let mut settings = Settings::default();
+        for line in source.lines() {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let value = match parts[..] {
+                [_, value] => value.parse::<f32>().ok().map(|v| v.clamp(0.0, 1.0)),
+                [] => continue,
+                _ => None,
+            };
+            match (parts[0], value) {
+                ("master", Some(v)) => settings.master = v,
+                ("music", Some(v)) => settings.music = v,
+                ("effects", Some(v)) => settings.effects = v,
+                ("interface", Some(v)) => settings.interface = v,
+                ("muted", Some(v)) => settings.muted = v != 0.0,
+                _ => println!("Invalid line in settings: {}", line),
+            }
+        }
+        settings
+    }
+
+    fn format(&self) -> String {
+        format!(
+            "master {}\nmusic {}\neffects {}\ninterface {}\nmuted {}\n",
+            self.master, self.music, self.effects, self.interface, self.muted as u8
+        )
+    }
+
+    // The volume to play a sound at
+    pub fn get_volume(&self, channel: Channel) -> f32 {
+        if self.muted {
+            return 0.0;
+        }
+        self.master
+            * match channel {
+                Channel::Music => self.music,
+                Channel::Effects => self.effects,
+                Channel::Interface => self.interface,
+            }
+    }
+
+    // Volumes in the order of the menu's rows
+    fn get_volume_setting(&self, index: usize) -> f32 {
+        match index {
+            0 => self.master,
+            1 => self.music,
+            2 => self.effects,
+            _ => self.interface,
+        }
+    }
+
+    fn set_volume_setting(&mut self, index: usize, volume: f32) {
+        match index {
+            0 => self.master = volume,
+            1 => self.music = volume,
+            2 => self.effects = volume,
+            _ => self.interface = volume,
+        }
+    }
+}
+
+// Keys and English names of the menu's rows, in order
+const MENU_ROWS: [(&str, &str); 4] = [
+    ("settings.master", "Volume"),
+    ("settings.music", "Music"),
+    ("settings.effects", "Effects"),
+    ("settings.interface", "Interface"),
+];
+
+// Up and down choose a volume, and left and right change it
+pub struct SettingsMenu {
+    selected: usize,
+}
+
+impl SettingsMenu {
+    pub fn new() -> SettingsMenu {
+        SettingsMenu { selected: 0 }
+    }
+
+    // Returns true if the settings changed
+    pub fn key_down(&mut self, settings: &mut Settings, keycode: KeyCode) -> bool {
+        match keycode {
+            KeyCode::Up | KeyCode::W => {
+                self.selected = (self.selected + MENU_ROWS.len() - 1) % MENU_ROWS.len();
+                false
+            }
+            KeyCode::Down | KeyCode::S => {
+                self.selected = (self.selected + 1) % MENU_ROWS.len();
+                false
+            }
+            KeyCode::Left | KeyCode::A | KeyCode::Right | KeyCode::D => {
+                let step = if keycode == KeyCode::Left || keycode == KeyCode::A {
+                    -VOLUME_STEP
+                } else {
+                    VOLUME_STEP
+                };
+                let volume = settings.get_volume_setting(self.selected) + step;
+                // Round so the steps stay even
+                let volume = ((volume / VOLUME_STEP).round() * VOLUME_STEP).clamp(0.0, 1.0);
+                settings.set_volume_setting(self.selected, volume);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Draw in the middle of the UI
+    pub fn draw(
+        &self,
+        ctx: &mut Context,
+        font: graphics::Font,
+        ui_rect: graphics::Rect,
+        settings: &Settings,
+        strings: &Strings,
+    ) -> GameResult {
+        let scale = graphics::Scale::uniform(FONT_SIZE);
+        let mut rows = Vec::new();
+        for (index, (key, english)) in MENU_ROWS.iter().enumerate() {
+            let mut name = graphics::Text::new(strings.get(key, english));
+            name.set_font(font, scale);
+            let volume = settings.get_volume_setting(index);
+            let mut value = graphics::Text::new(format!("{:.0}%", volume * 100.0));
+            value.set_font(font, scale);
+            rows.push((name, value));
+        }
+        if settings.muted {
+            let mut name = graphics::Text::new(strings.get("settings.muted", "Muted (M)"));
+            name.set_font(font, scale);
+            rows.push((name, graphics::Text::new("")));
+        }
+
+        let row_height = rows[0].0.height(ctx) as f32;
+        let mut width = 0.0f32;
+        for (name, value) in rows.iter() {
+            width = width
+                .max(name.width(ctx) as f32)
+                .max(VALUE_COLUMN + value.width(ctx) as f32);
+        }
+        let height = row_height * rows.len() as f32;
+        let panel = graphics::Rect::new(
+            ui_rect.x + (ui_rect.w - width) * 0.5 - PADDING,
+            ui_rect.y + (ui_rect.h - height) * 0.5 - PADDING,
+            width + PADDING * 2.0,
+            height + PADDING * 2.0,
+        );
+        draw_panel(ctx, panel)?;
+
+        for (index, (name, value)) in rows.iter().enumerate() {
+            let color = Color::from_rgb_u32(if index == self.selected {
+                SELECTED_COLOR
+            } else {
+                TEXT_COLOR
+            });
+            let x = panel.x + PADDING;
+            let y = panel.y + PADDING + row_height * index as f32;
+            let param = graphics::DrawParam::default().color(color);
+            graphics::draw(ctx, name, param.dest(Point2::new(x, y)))?;
+            graphics::draw(ctx, value, param.dest(Point2::new(x + VALUE_COLUMN, y)))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_round_trip() {
+        let mut settings = Settings::default();
+        settings.set_volume_setting(1, 0.5);
+        settings.set_volume_setting(3, 0.25);
+        settings.muted = true;
+        let source = settings.format();
+        assert_eq!(
+            source,
+            "master 1\nmusic 0.5\neffects 1\ninterface 0.25\nmuted 1\n"
+        );
+
+        let loaded = Settings::parse(&source);
+        assert_eq!(loaded.format(), source);
+        assert_eq!(loaded.get_volume(Channel::Music), 0.0);
+    }
+
+    #[test]
+    fn volumes_are_clamped_and_invalid_lines_skipped() {
+        let settings = Settings::parse("master 2\nmusic -1\neffects loud\nbass 1\n\ninterface 0.5");
+        assert_eq!(settings.get_volume(Channel::Music), 0.0);
+        assert_eq!(settings.get_volume(Channel::Effects), 1.0);
+        assert_eq!(settings.get_volume(Channel::Interface), 0.5);
+        assert!(!settings.muted);
+    }
+}
@@ -0,0 +1,146 @@
+// Sounds of the ship: thrust that follows the engine and the speed, and a
+// warning that beeps faster the closer the ship is to a wall.
+//
+// Sources can't change pitch while playing, so the thrust is a few copies of
+// the sound at different pitches, and the one closest to the wanted pitch is
+// the loudest.
+
+use ggez::audio;
+use ggez::audio::SoundSource;
+use ggez::{Context, GameResult};
+
+// Pitches of the thrust layers, lowest first
+const THRUST_PITCHES: [f32; 3] = [0.75, 1.0, 1.35];
+// How fast the engine spools up and down, per second
+const THRUST_ATTACK: f32 = 8.0;
+const THRUST_RELEASE: f32 = 3.0;
+// Pitch at idle and at full thrust, and how much faster it gets at
+// SPEED_FOR_PITCH
+const IDLE_PITCH: f32 = 0.8;
+const FULL_THRUST_PITCH: f32 = 1.0;
+const SPEED_PITCH: f32 = 0.3;
+const SPEED_FOR_PITCH: f32 = 200.0;
+// Volume at full thrust, and the extra volume at SPEED_FOR_PITCH
+const THRUST_VOLUME: f32 = 0.3;
+const SPEED_VOLUME: f32 = 0.1;
+
+// Walls closer than this make the warning beep
+pub const WARNING_DISTANCE: f32 = 40.0;
+// Seconds between beeps when far and close
+const SLOWEST_BEEPS: f32 = 0.6;
+const FASTEST_BEEPS: f32 = 0.1;
+const BEEP_FREQUENCY: f32 = 880.0;
+const BEEP_LENGTH: f32 = 0.06;
+const BEEP_VOLUME: f32 = 0.25;
+const SAMPLE_RATE: u32 = 22050;
+
+// A short sine tone as a WAV file
+fn create_beep() -> Vec<u8> {
+    let sample_count = (BEEP_LENGTH * SAMPLE_RATE as f32) as u32;
+    let mut wav = Vec::new();
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + sample_count * 2).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // Mono
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(sample_count * 2).to_le_bytes());
+    for i in 0..sample_count {
+        let t = i as f32 / SAMPLE_RATE as f32;
+        // Fade in and out so it doesn't click
+        let envelope = (t / BEEP_LENGTH * std::f32::consts::PI).sin();
+        let value = (t * BEEP_FREQUENCY * std::f32::consts::PI * 2.0).sin() * envelope;
+        wav.extend_from_slice(&((value * i16::MAX as f32) as i16).to_le_bytes());
+    }
+    wav
+}
+
+pub struct ShipAudio {
+    thrust_layers: Vec<audio::Source>,
+    // Follows the thrust, but not instantly
+    envelope: f32,
+    warning: audio::Source,
+    // Seconds since the last beep
+    since_beep: f32,
+}
+
+impl ShipAudio {
+    pub fn new(ctx: &mut Context) -> GameResult<ShipAudio> {
+        let mut thrust_layers = Vec::new();
+        for pitch in THRUST_PITCHES.iter() {
+            let mut source = audio::Source::new(ctx, "/thrust.wav")?;
+            source.set_pitch(*pitch);
+            source.set_repeat(true);
+            source.set_volume(0.0);
+            source.play()?;
+            thrust_layers.push(source);
+        }
+        let warning = audio::Source::from_data(ctx, audio::SoundData::from_bytes(&create_beep()))?;
+        Ok(ShipAudio {
+            thrust_layers,
+            envelope: 0.0,
+            warning,
+            since_beep: 0.0,
+        })
+    }
+
+    // thrust is from 0 to 1 and speed is in world units per second.
+    // volume is the effects volume, or 0 to be quiet right away.
+    pub fn update(
+        &mut self,
+        delta_time: f32,
+        thrust: f32,
+        speed: f32,
+        wall_distance: Option<f32>,
+        volume: f32,
+    ) {
+        let rate = if thrust > self.envelope {
+            THRUST_ATTACK
+        } else {
+            THRUST_RELEASE
+        };
+        let step = rate * delta_time;
+        self.envelope += (thrust - self.envelope).clamp(-step, step);
+
+        let speed_factor = (speed / SPEED_FOR_PITCH).min(1.0);
+        let pitch = IDLE_PITCH
+            + (FULL_THRUST_PITCH - IDLE_PITCH) * self.envelope
+            + SPEED_PITCH * speed_factor;
+        let thrust_volume = self.envelope * (THRUST_VOLUME + SPEED_VOLUME * speed_factor) * volume;
+        // Mix the two layers on each side of the pitch
+        let last = THRUST_PITCHES.len() - 1;
+        let upper = THRUST_PITCHES
+            .iter()
+            .position(|&p| p >= pitch)
+            .unwrap_or(last)
+            .max(1);
+        let (low, high) = (THRUST_PITCHES[upper - 1], THRUST_PITCHES[upper]);
+        let mix = ((pitch - low) / (high - low)).clamp(0.0, 1.0);
+        for (i, source) in self.thrust_layers.iter_mut().enumerate() {
+            let weight = if i == upper {
+                mix
+            } else if i == upper - 1 {
+                1.0 - mix
+            } else {
+                0.0
+            };
+            source.set_volume(thrust_volume * weight);
+        }
+
+        self.since_beep += delta_time;
+        if let Some(distance) = wall_distance.filter(|_| volume > 0.0) {
+            let closeness = (1.0 - distance / WARNING_DISTANCE).clamp(0.0, 1.0);
+            let interval = SLOWEST_BEEPS + (FASTEST_BEEPS - SLOWEST_BEEPS) * closeness;
+            if self.since_beep >= interval {
+                self.since_beep = 0.0;
+                self.warning.set_volume(BEEP_VOLUME * volume);
+                let _ = self.warning.play();
+            }
+        }
+    }
+}
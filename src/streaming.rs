@@ -0,0 +1,245 @@
+// Streaming of level sections.
+//
+// Levels listed on the same line in `/worlds.txt` are sections of one
+// continuous world, e.g.
+//
+//     # The caves
+//     4 5 6
+//
+// The sections share coordinate space, so their extents should be adjacent
+// but not overlapping. When the ship gets close to a section, its files are
+// read in the background and it is loaded, and sections that are far away
+// are unloaded. Flying into another section makes it the current level
+// without any text in between.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+use ggez::nalgebra::Point2;
+use ggez::{GameError, GameResult};
+
+use ld45::meshes::{Extents, RawMeshes};
+use ld45::script::Script;
+
+// Distance from a section's extents where it gets loaded and unloaded
+pub const LOAD_DISTANCE: f32 = 400.0;
+pub const UNLOAD_DISTANCE: f32 = 800.0;
+
+// Each world is a list of level numbers
+pub fn parse_worlds(source: &str) -> GameResult<Vec<Vec<u32>>> {
+    let mut worlds = Vec::new();
+    for (line_index, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let levels = line
+            .split_whitespace()
+            .map(|n| n.parse::<u32>())
+            .collect::<Result<Vec<u32>, _>>()
+            .map_err(|_| {
+                GameError::ResourceLoadError(format!(
+                    "Worlds line {}: Invalid level number: {}",
+                    line_index + 1,
+                    line
+                ))
+            })?;
+        worlds.push(levels);
+    }
+    Ok(worlds)
+}
+
+// The files of a section, read by the loader thread
+pub struct SectionData {
+    pub level_number: u32,
+    pub raw_meshes: RawMeshes,
+    pub script: Script,
+}
+
+fn read_file(dirs: &[PathBuf], name: &str) -> GameResult<Vec<u8>> {
+    match dirs.iter().map(|dir| dir.join(name)).find(|p| p.exists()) {
+        Some(path) => Ok(fs::read(path)?),
+        None => Err(GameError::ResourceNotFound(name.to_string(), Vec::new())),
+    }
+}
+
+fn read_section(dirs: &[PathBuf], level_number: u32) -> GameResult<SectionData> {
+    let raw_meshes =
+        RawMeshes::decode(&read_file(dirs, &format!("level{:02}.dat", level_number))?)?;
+    raw_meshes.validate()?;
+    let source = read_file(dirs, &format!("level{:02}.script", level_number))?;
+    let script = Script::parse(&String::from_utf8_lossy(&source))?;
+    Ok(SectionData {
+        level_number,
+        raw_meshes,
+        script,
+    })
+}
+
+fn distance_to_rect(extents: &Extents, position: Point2<f32>) -> f32 {
+    let dx = (extents.min_x - position.x)
+        .max(position.x - extents.max_x)
+        .max(0.0);
+    let dy = (extents.min_y - position.y)
+        .max(position.y - extents.max_y)
+        .max(0.0);
+    (dx * dx + dy * dy).sqrt()
+}
+
+// What has happened in a section, kept when it is unloaded
+#[derive(Debug, Default)]
+pub struct SectionState {
+    pub shown_triggers: HashSet<u32>,
+    pub group_enabled: Vec<bool>,
+}
+
+pub struct Streamer {
+    worlds: Vec<Vec<u32>>,
+    // The sections of the world the current level belongs to
+    sections: Vec<u32>,
+    // Extents of sections that have been read at least once
+    extents: HashMap<u32, Extents>,
+    // Sections that the loader thread is working on
+    requested: HashSet<u32>,
+    // State of sections that have been unloaded
+    states: HashMap<u32, SectionState>,
+    requests: Sender<u32>,
+    results: Receiver<(u32, Result<SectionData, String>)>,
+}
+
+impl Streamer {
+    pub fn new(dirs: Vec<PathBuf>, worlds: Vec<Vec<u32>>) -> Streamer {
+        let (requests, request_receiver) = channel::<u32>();
+        let (result_sender, results) = channel();
+        // Stops when the Streamer is dropped
+        thread::spawn(move || {
+            for level_number in request_receiver {
+                let result = read_section(&dirs, level_number).map_err(|e| e.to_string());
+                if result_sender.send((level_number, result)).is_err() {
+                    break;
+                }
+            }
+        });
+        Streamer {
+            worlds,
+            sections: Vec::new(),
+            extents: HashMap::new(),
+            requested: HashSet::new(),
+            states: HashMap::new(),
+            requests,
+            results,
+        }
+    }
+
+    // Called when a level is loaded the normal way, i.e. not streamed.
+    // Starts reading the other sections of its world to find their extents.
+    pub fn enter(&mut self, level_number: u32, extents: Extents) {
+        self.sections = self
+            .worlds
+            .iter()
+            .find(|world| world.contains(&level_number))
+            .cloned()
+            .unwrap_or_default();
+        self.states.clear();
+        self.extents.insert(level_number, extents);
+        for section in self.sections.clone() {
+            if !self.extents.contains_key(&section) {
+                self.request(section);
+            }
+        }
+    }
+
+    fn request(&mut self, level_number: u32) {
+        if self.requested.insert(level_number) {
+            let _ = self.requests.send(level_number);
+        }
+    }
+
+    // Get the sections that the loader thread has finished reading
+    pub fn receive(&mut self) -> Vec<SectionData> {
+        let mut received = Vec::new();
+        while let Ok((level_number, result)) = self.results.try_recv() {
+            self.requested.remove(&level_number);
+            match result {
+                Ok(data) => {
+                    self.extents
+                        .insert(level_number, data.raw_meshes.get_extents());
+                    received.push(data);
+                }
+                Err(e) => println!("Failed to read section {}: {}", level_number, e),
+            }
+        }
+        received
+    }
+
+    // Whether a section of the current world is within distance of a position
+    pub fn is_near(&self, level_number: u32, position: Point2<f32>, distance: f32) -> bool {
+        self.sections.contains(&level_number)
+            && self
+                .extents
+                .get(&level_number)
+                .is_some_and(|e| distance_to_rect(e, position) <= distance)
+    }
+
+    // Start reading the sections close to a position that aren't loaded
+    pub fn request_near(&mut self, position: Point2<f32>, loaded: &HashSet<u32>) {
+        let wanted: Vec<u32> = self
+            .sections
+            .iter()
+            .copied()
+            .filter(|n| !loaded.contains(n) && self.is_near(*n, position, LOAD_DISTANCE))
+            .collect();
+        for level_number in wanted {
+            self.request(level_number);
+        }
+    }
+
+    // Remember the state of a section that is unloaded
+    pub fn store_state(&mut self, level_number: u32, state: SectionState) {
+        self.states.insert(level_number, state);
+    }
+
+    pub fn take_state(&mut self, level_number: u32) -> Option<SectionState> {
+        self.states.remove(&level_number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_worlds_skips_comments_and_empty_lines() {
+        let worlds = parse_worlds("# The caves\n4 5  6\n\n  # Sky\n7\n").unwrap();
+        assert_eq!(worlds, vec![vec![4, 5, 6], vec![7]]);
+        assert!(parse_worlds("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_worlds_rejects_invalid_numbers() {
+        match parse_worlds("1 2\n3 x") {
+            Err(GameError::ResourceLoadError(message)) => {
+                assert_eq!(message, "Worlds line 2: Invalid level number: 3 x")
+            }
+            _ => panic!("Expected an error"),
+        }
+    }
+
+    #[test]
+    fn distance_to_rect_is_0_inside() {
+        let extents = Extents {
+            min_x: 0.0,
+            max_x: 10.0,
+            min_y: 0.0,
+            max_y: 10.0,
+            collision_width: 10,
+            collision_height: 10,
+        };
+        assert_eq!(distance_to_rect(&extents, Point2::new(5.0, 5.0)), 0.0);
+        assert_eq!(distance_to_rect(&extents, Point2::new(5.0, 13.0)), 3.0);
+        assert_eq!(distance_to_rect(&extents, Point2::new(13.0, -4.0)), 5.0);
+    }
+}
@@ -0,0 +1,127 @@
+// Translations of the game's texts.
+//
+// The texts in the level scripts and the game are in English. A language is
+// chosen with `--lang <code>`, and its texts are read from `/lang/<code>.txt`,
+// which has one text per line:
+//
+//     # Comment
+//     font = /font/some-font.ttf
+//     ouch = Aïe! ... Pourquoi est-ce que ça me semble familier ?
+//     What's this? What happened? Am I falling? = Qu'est-ce que c'est ?
+//
+// `font` replaces `/font/font.ttf`, e.g. for scripts it doesn't have. A text
+// from a script is named by the English text as it is written in the script,
+// so it stays translated when lines are added or moved, but not when the
+// English text changes. The game's own texts have short names like `ouch`.
+// `\n` is a line break. Texts that aren't translated are shown in English.
+
+use std::collections::HashMap;
+use std::io::Read;
+
+use ggez::filesystem;
+use ggez::{Context, GameError, GameResult};
+
+pub const DEFAULT_LANGUAGE: &str = "en";
+pub const DEFAULT_FONT: &str = "/font/font.ttf";
+
+#[derive(Debug, Default)]
+pub struct Strings {
+    texts: HashMap<String, String>,
+    font: Option<String>,
+}
+
+impl Strings {
+    pub fn parse(source: &str) -> GameResult<Strings> {
+        let mut strings = Strings::default();
+        for (line_index, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            // English texts may contain '=' but not " = "
+            let (key, text) = line.split_once(" = ").ok_or_else(|| {
+                GameError::ResourceLoadError(format!(
+                    "Strings line {}: Missing ' = ': {}",
+                    line_index + 1,
+                    line
+                ))
+            })?;
+            let (key, text) = (key.trim(), text.trim());
+            if key == "font" {
+                strings.font = Some(text.to_string());
+            } else {
+                strings
+                    .texts
+                    .insert(key.replace("\\n", "\n"), text.replace("\\n", "\n"));
+            }
+        }
+        Ok(strings)
+    }
+
+    // Read the texts of a language. Without a file, everything is in English.
+    pub fn load(ctx: &mut Context, language: &str) -> GameResult<Strings> {
+        let path = format!("/lang/{}.txt", language);
+        if !filesystem::exists(ctx, &path) {
+            if language != DEFAULT_LANGUAGE {
+                println!("No texts for language {}", language);
+            }
+            return Ok(Strings::default());
+        }
+        let mut source = String::new();
+        filesystem::open(ctx, &path)?.read_to_string(&mut source)?;
+        Strings::parse(&source)
+    }
+
+    // The translation of a text, or the English text if there is none
+    pub fn get<'a>(&'a self, key: &str, english: &'a str) -> &'a str {
+        self.texts.get(key).map_or(english, |t| t.as_str())
+    }
+
+    pub fn get_font(&self) -> &str {
+        self.font.as_deref().unwrap_or(DEFAULT_FONT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_texts_and_font() {
+        let strings = Strings::parse(
+            "# Comment\n\
+             \n\
+             font = /font/other.ttf\n\
+             ouch = Aïe!\n\
+             Where am I?\\nHello = Où suis-je ?\\nBonjour\n\
+             a=b = c\n",
+        )
+        .unwrap();
+        assert_eq!(strings.get_font(), "/font/other.ttf");
+        assert_eq!(strings.get("ouch", "Ouch!"), "Aïe!");
+        assert_eq!(
+            strings.get("Where am I?\nHello", "Where am I?\nHello"),
+            "Où suis-je ?\nBonjour"
+        );
+        assert_eq!(strings.get("a=b", "a=b"), "c");
+        // Untranslated texts stay in English
+        assert_eq!(strings.get("thrust", "Thrust"), "Thrust");
+    }
+
+    #[test]
+    fn default_is_english() {
+        let strings = Strings::default();
+        assert_eq!(strings.get_font(), DEFAULT_FONT);
+        assert_eq!(strings.get("ouch", "Ouch!"), "Ouch!");
+    }
+
+    #[test]
+    fn parse_rejects_lines_without_separator() {
+        match Strings::parse("ouch = Aïe!\nouch=Aïe!") {
+            Err(GameError::ResourceLoadError(message)) => {
+                assert!(message.starts_with("Strings line 2: Missing ' = '"))
+            }
+            _ => panic!("Expected an error"),
+        }
+    }
+}
@@ -0,0 +1,129 @@
+// Subtitles: lines of text at the bottom of the screen that type themselves
+// out while the game goes on, and go away by themselves. Long lines are
+// wrapped like the texts in src/messages.rs.
+
+use std::collections::VecDeque;
+
+use ggez::graphics;
+use ggez::graphics::Color;
+use ggez::nalgebra::Point2;
+use ggez::{Context, GameResult};
+
+use crate::messages::wrap;
+
+// Characters typed per second
+const TYPING_SPEED: f32 = 40.0;
+// How long a subtitle stays after it has been typed out: a minimum plus
+// reading time per character
+const MIN_DURATION: f32 = 1.5;
+const DURATION_PER_CHARACTER: f32 = 0.05;
+
+const FONT_SIZE: f32 = 22.0;
+const COLOR: u32 = 0xe0ffe0;
+// Distance from the bottom of the screen in UI coordinates
+const MARGIN: f32 = 20.0;
+// Width of the lines in UI coordinates. The UI is 800 wide.
+const WIDTH: f32 = 680.0;
+
+struct Subtitle {
+    // Wrapped to fit WIDTH
+    lines: Vec<String>,
+    // Seconds since it started typing
+    age: f32,
+}
+
+impl Subtitle {
+    fn get_length(&self) -> usize {
+        self.lines.iter().map(|l| l.chars().count()).sum()
+    }
+
+    fn get_duration(&self) -> f32 {
+        let length = self.get_length() as f32;
+        length / TYPING_SPEED + MIN_DURATION + length * DURATION_PER_CHARACTER
+    }
+}
+
+pub struct Subtitles {
+    current: Option<Subtitle>,
+    // Subtitles that are shown when the current one is done
+    waiting: VecDeque<Vec<String>>,
+}
+
+impl Subtitles {
+    pub fn new() -> Subtitles {
+        Subtitles {
+            current: None,
+            waiting: VecDeque::new(),
+        }
+    }
+
+    pub fn add(&mut self, ctx: &mut Context, font: graphics::Font, text: &str) {
+        let mut measure = |text: &str| {
+            let mut t = graphics::Text::new(text);
+            t.set_font(font, graphics::Scale::uniform(FONT_SIZE));
+            t.width(ctx) as f32
+        };
+        let lines = text
+            .split('\n')
+            .flat_map(|line| wrap(&mut measure, line, WIDTH, WIDTH))
+            .collect();
+        self.waiting.push_back(lines);
+        if self.current.is_none() {
+            self.next();
+        }
+    }
+
+    fn next(&mut self) {
+        self.current = self
+            .waiting
+            .pop_front()
+            .map(|lines| Subtitle { lines, age: 0.0 });
+    }
+
+    pub fn update(&mut self, delta_time: f32) {
+        if let Some(subtitle) = self.current.as_mut() {
+            subtitle.age += delta_time;
+            if subtitle.age >= subtitle.get_duration() {
+                self.next();
+            }
+        }
+    }
+
+    // Draw in UI coordinates
+    pub fn draw(
+        &self,
+        ctx: &mut Context,
+        font: graphics::Font,
+        ui_rect: graphics::Rect,
+    ) -> GameResult {
+        let subtitle = match self.current.as_ref() {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+        let scale = graphics::Scale::uniform(FONT_SIZE);
+        let color = Color::from_rgb_u32(COLOR);
+        let mut typed = (subtitle.age * TYPING_SPEED) as usize;
+        // As high as a line with text
+        let line_height = graphics::Text::new(" ").set_font(font, scale).height(ctx) as f32;
+        let mut y = ui_rect.y + ui_rect.h - MARGIN - line_height * subtitle.lines.len() as f32;
+        for line in subtitle.lines.iter() {
+            let length = line.chars().count();
+            let mut text = graphics::Text::new(line.chars().take(typed).collect::<String>());
+            text.set_font(font, scale);
+            typed = typed.saturating_sub(length);
+            // Position by the whole line so it doesn't move while typing
+            let mut full_text = graphics::Text::new(line.as_str());
+            full_text.set_font(font, scale);
+            let width = full_text.width(ctx) as f32;
+            graphics::draw(
+                ctx,
+                &text,
+                graphics::DrawParam::default()
+                    .dest(Point2::new(ui_rect.x + (ui_rect.w - width) * 0.5, y))
+                    .color(color),
+            )?;
+            y += line_height;
+        }
+        Ok(())
+    }
+}
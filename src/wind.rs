@@ -0,0 +1,247 @@
+// Wind and currents: the force they put on the ship, and particles that
+// drift with them to show where they blow.
+
+use ggez::graphics;
+use ggez::graphics::Color;
+use ggez::nalgebra::{Point2, Vector2};
+use ggez::{Context, GameResult};
+
+use ld45::meshes::{polygon_area, WindField};
+
+// Size of the turbulence pattern in world units, and how many times per
+// second it changes
+const TURBULENCE_SCALE: f32 = 60.0;
+const TURBULENCE_SPEED: f32 = 0.5;
+
+const AREA_PER_PARTICLE: f32 = 400.0;
+const MAX_PARTICLES_PER_FIELD: usize = 200;
+const PARTICLE_LIFETIME: f32 = 2.0;
+// Particles move with the wind's acceleration times this many seconds
+const PARTICLE_DRIFT: f32 = 1.0;
+// Length of a particle's streak, in seconds of movement
+const PARTICLE_STREAK: f32 = 0.15;
+const PARTICLE_WIDTH: f32 = 1.0;
+const PARTICLE_COLOR: u32 = 0xb0e0e0;
+
+fn hash(seed: u32, x: i32, y: i32, z: i32) -> u32 {
+    let mut h = seed
+        .wrapping_mul(0x9e37_79b9)
+        .wrapping_add((x as u32).wrapping_mul(0x85eb_ca6b))
+        .wrapping_add((y as u32).wrapping_mul(0xc2b2_ae35))
+        .wrapping_add((z as u32).wrapping_mul(0x27d4_eb2f));
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297a_2d39);
+    h ^ (h >> 15)
+}
+
+// Random value between -1 and 1 at a lattice point
+fn lattice(seed: u32, x: i32, y: i32, z: i32) -> f32 {
+    hash(seed, x, y, z) as f32 / u32::MAX as f32 * 2.0 - 1.0
+}
+
+// Smooth value noise between -1 and 1. Always the same for the same arguments.
+fn noise(seed: u32, x: f32, y: f32, z: f32) -> f32 {
+    let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+    let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+    let (fx, fy, fz) = (smooth(x - x0), smooth(y - y0), smooth(z - z0));
+    let (x0, y0, z0) = (x0 as i32, y0 as i32, z0 as i32);
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let plane = |z: i32| {
+        lerp(
+            lerp(lattice(seed, x0, y0, z), lattice(seed, x0 + 1, y0, z), fx),
+            lerp(
+                lattice(seed, x0, y0 + 1, z),
+                lattice(seed, x0 + 1, y0 + 1, z),
+                fx,
+            ),
+            fy,
+        )
+    };
+    lerp(plane(z0), plane(z0 + 1), fz)
+}
+
+// The acceleration from a field at a position inside it. time is in seconds.
+pub fn get_force(field: &WindField, position: Point2<f32>, time: f32) -> Vector2<f32> {
+    let mut force = Vector2::new(field.force.0, field.force.1);
+    if field.turbulence != 0.0 {
+        let x = position.x / TURBULENCE_SCALE;
+        let y = position.y / TURBULENCE_SCALE;
+        let t = time * TURBULENCE_SPEED;
+        force += field.turbulence
+            * Vector2::new(
+                noise(field.seed, x, y, t),
+                noise(field.seed.wrapping_add(1), x, y, t),
+            );
+    }
+    force
+}
+
+// The acceleration from all fields that contain a position
+pub fn get_wind(fields: &[WindField], position: Point2<f32>, time: f32) -> Vector2<f32> {
+    fields
+        .iter()
+        .filter(|field| field.contains(position.x, position.y))
+        .fold(Vector2::new(0.0, 0.0), |sum, field| {
+            sum + get_force(field, position, time)
+        })
+}
+
+struct Particle {
+    position: Point2<f32>,
+    velocity: Vector2<f32>,
+    age: f32,
+}
+
+pub struct WindParticles {
+    // Particles of each wind field
+    particles: Vec<Vec<Particle>>,
+    random_state: u32,
+}
+
+// Random number between 0 and 1 (xorshift)
+fn random(state: &mut u32) -> f32 {
+    *state ^= *state << 13;
+    *state ^= *state >> 17;
+    *state ^= *state << 5;
+    *state as f32 / u32::MAX as f32
+}
+
+impl WindParticles {
+    pub fn new() -> WindParticles {
+        WindParticles {
+            particles: Vec::new(),
+            random_state: 0x1234_5678,
+        }
+    }
+
+    pub fn update(&mut self, fields: &[WindField], time: f32, delta_time: f32) {
+        self.particles.resize_with(fields.len(), Vec::new);
+        let random_state = &mut self.random_state;
+        for (field, particles) in fields.iter().zip(self.particles.iter_mut()) {
+            for p in particles.iter_mut() {
+                p.velocity = get_force(field, p.position, time) * PARTICLE_DRIFT;
+                p.position += p.velocity * delta_time;
+                p.age += delta_time;
+            }
+            particles.retain(|p| {
+                p.age < PARTICLE_LIFETIME && field.contains(p.position.x, p.position.y)
+            });
+
+            // Spawn new particles at random places in the field. The first
+            // ones get random ages so they don't all disappear at once.
+            let first = particles.is_empty();
+            let wanted = ((polygon_area(&field.polygon).abs() / AREA_PER_PARTICLE) as usize)
+                .clamp(1, MAX_PARTICLES_PER_FIELD);
+            let min_x = field
+                .polygon
+                .iter()
+                .map(|p| p.0)
+                .fold(f32::INFINITY, f32::min);
+            let max_x = field
+                .polygon
+                .iter()
+                .map(|p| p.0)
+                .fold(f32::NEG_INFINITY, f32::max);
+            let min_y = field
+                .polygon
+                .iter()
+                .map(|p| p.1)
+                .fold(f32::INFINITY, f32::min);
+            let max_y = field
+                .polygon
+                .iter()
+                .map(|p| p.1)
+                .fold(f32::NEG_INFINITY, f32::max);
+            let mut attempts = wanted * 4;
+            while particles.len() < wanted && attempts > 0 {
+                attempts -= 1;
+                let position = Point2::new(
+                    min_x + random(random_state) * (max_x - min_x),
+                    min_y + random(random_state) * (max_y - min_y),
+                );
+                if !field.contains(position.x, position.y) {
+                    continue;
+                }
+                particles.push(Particle {
+                    position,
+                    velocity: Vector2::new(0.0, 0.0),
+                    age: if first {
+                        random(random_state) * PARTICLE_LIFETIME
+                    } else {
+                        0.0
+                    },
+                });
+            }
+        }
+    }
+
+    // Draw in world coordinates
+    pub fn draw(&self, ctx: &mut Context) -> GameResult {
+        let mut builder = graphics::MeshBuilder::new();
+        let mut count = 0;
+        for p in self.particles.iter().flatten() {
+            let tail = p.position - p.velocity * PARTICLE_STREAK;
+            if tail == p.position {
+                continue;
+            }
+            // Fade in and out
+            let alpha = (p.age / PARTICLE_LIFETIME * std::f32::consts::PI).sin();
+            let mut color = Color::from_rgb_u32(PARTICLE_COLOR);
+            color.a = alpha * 0.6;
+            builder.line(&[tail, p.position], PARTICLE_WIDTH, color)?;
+            count += 1;
+        }
+        if count != 0 {
+            let mesh = builder.build(ctx)?;
+            graphics::draw(ctx, &mesh, graphics::DrawParam::default())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(seed: u32) -> WindField {
+        WindField {
+            polygon: vec![(0.0, 0.0), (100.0, 0.0), (100.0, 100.0), (0.0, 100.0)],
+            force: (3.0, 0.0),
+            turbulence: 2.0,
+            seed,
+        }
+    }
+
+    #[test]
+    fn noise_is_smooth_and_in_range() {
+        for i in 0..1000 {
+            let x = i as f32 * 0.37;
+            let value = noise(7, x, -x * 0.5, x * 0.1);
+            assert!((-1.0..=1.0).contains(&value));
+        }
+        // Lattice points are exact and the noise is continuous between them
+        assert_eq!(noise(7, 2.0, 3.0, 4.0), lattice(7, 2, 3, 4));
+        assert!((noise(7, 2.999, 3.0, 4.0) - noise(7, 3.0, 3.0, 4.0)).abs() < 0.01);
+    }
+
+    #[test]
+    fn turbulence_depends_only_on_seed_position_and_time() {
+        let position = Point2::new(42.0, 17.0);
+        let a = get_force(&field(1), position, 3.5);
+        assert_eq!(a, get_force(&field(1), position, 3.5));
+        assert_ne!(a, get_force(&field(2), position, 3.5));
+        assert_ne!(a, get_force(&field(1), position, 4.5));
+        assert!((a.x - 3.0).abs() <= 2.0 && a.y.abs() <= 2.0);
+    }
+
+    #[test]
+    fn wind_is_only_inside_fields() {
+        let fields = [field(1)];
+        let outside = get_wind(&fields, Point2::new(-1.0, 50.0), 0.0);
+        assert_eq!(outside, Vector2::new(0.0, 0.0));
+        let inside = get_wind(&fields, Point2::new(50.0, 50.0), 0.0);
+        assert_eq!(inside, get_force(&fields[0], Point2::new(50.0, 50.0), 0.0));
+    }
+}